@@ -1,18 +1,49 @@
-use git2::BranchType;
-use git2::{AutotagOption, FetchOptions, PushOptions, RemoteCallbacks};
+use git2::build::RepoBuilder;
+use git2::{BranchType, ObjectType};
+use git2::{FetchOptions, Signature};
 use serde::{Deserialize, Serialize};
-use std::io::{self, Write};
 use std::path::Path;
 use std::{fs, str, vec};
-use tauri::command;
+use tauri::{command, Window};
 
+use crate::commit_message;
 use crate::db;
+use crate::diff;
 use crate::error::{GitError, SledError};
 use crate::git;
-use crate::state::{AppArg, FileStatus, GitDiff, MyBranchType, MyState, Stats};
-use crate::utils::{
-    get_absolute_path_from_relative, get_origin_and_current_name_from_line, path_is_file,
+use crate::highlight;
+use crate::hooks::{Hook, Hooks};
+use crate::image_diff;
+use crate::progress::ProgressReporter;
+use crate::signing;
+use crate::state::{
+    AppArg, CommitRules, CommitSignature, CommitViolation, DiffFile, DiffSettings,
+    DiscardSelection, DiscardSource, FetchSummary, FileStats, FileStatus, Identity, ImageDiff,
+    MyBranchType, MyState, RemoteInfo, RemoteOperation, RevisionDiff, RevisionRange, Stats,
+    TagInfo,
 };
+use crate::utils::path_is_file;
+
+// A handle of its own for the command, the shared one stays locked only while
+// reopening it so slow commands don't hold up the others
+fn repo_handle(state: &MyState) -> Result<git::Repo, GitError> {
+    match state.repo.lock().unwrap().as_ref() {
+        Some(repo) => repo.reopen(),
+        None => Err(GitError::RepoNotFound),
+    }
+}
+
+// Runs the work of a command on the blocking thread pool, keeping the async
+// runtime free for the others
+async fn run_blocking<T, F>(work: F) -> Result<T, GitError>
+where
+    F: FnOnce() -> Result<T, GitError> + Send + 'static,
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(work)
+        .await
+        .map_err(|e| GitError::Error(e.to_string()))?
+}
 
 #[command]
 pub fn open(state: AppArg, path: &str) -> Result<String, GitError> {
@@ -35,6 +66,44 @@ pub fn open(state: AppArg, path: &str) -> Result<String, GitError> {
     }
 }
 
+#[command]
+pub fn create_operation(state: AppArg) -> Result<u32, GitError> {
+    Ok(state.operations.create())
+}
+
+#[command]
+pub fn cancel_operation(state: AppArg, id: u32) -> Result<bool, GitError> {
+    Ok(state.operations.cancel(id))
+}
+
+#[command]
+pub async fn clone_repo(
+    state: AppArg<'_>,
+    window: Window,
+    url: String,
+    path: String,
+    operation_id: Option<u32>,
+) -> Result<String, GitError> {
+    let operation = state.operations.start(operation_id);
+    let token = operation.token.clone();
+    let repo = run_blocking(move || {
+        let progress = ProgressReporter::new(&window, RemoteOperation::Clone, &url, token);
+        let mut cb = git::get_remote_callbacks();
+        progress.attach(&mut cb);
+        let mut fo = FetchOptions::new();
+        fo.remote_callbacks(cb);
+        let repo = RepoBuilder::new()
+            .fetch_options(fo)
+            .clone(&url, Path::new(&path))
+            .map_err(|e| progress.error(e))?;
+        Ok(git::Repo::new(repo))
+    })
+    .await?;
+    let repo_path = repo.path();
+    *state.repo.lock().unwrap() = Some(repo);
+    Ok(repo_path)
+}
+
 #[command]
 pub fn find_branches(state: AppArg, filter: Option<MyBranchType>) -> Result<Vec<String>, GitError> {
     let repo = state.repo.clone();
@@ -104,11 +173,13 @@ pub fn checkout_branch(state: AppArg, branch_name: String) -> Result<(), GitErro
 }
 
 #[command]
-pub fn checkout_remote_branch(state: AppArg, branch_name: String) -> Result<(), GitError> {
-    let repo = state.repo.clone();
-    let repo = repo.lock().unwrap();
-    let repo = repo.as_ref();
-    if let Some(repo) = repo {
+pub async fn checkout_remote_branch(
+    state: AppArg<'_>,
+    branch_name: String,
+) -> Result<(), GitError> {
+    let repo = repo_handle(&state)?;
+    // Listing the remote branches goes over the network
+    run_blocking(move || {
         let mut remote = repo
             .repo
             .find_remote(git::DEFAULT_REMOTE)
@@ -144,9 +215,9 @@ pub fn checkout_remote_branch(state: AppArg, branch_name: String) -> Result<(),
         repo.repo
             .checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
 
-        return Ok(());
-    }
-    Err(GitError::RepoNotFound)
+        Ok(())
+    })
+    .await
 }
 
 #[command]
@@ -167,130 +238,248 @@ pub fn get_remotes(state: AppArg) -> Result<Vec<String>, GitError> {
 }
 
 #[command]
-pub fn fetch_remote(state: AppArg, remote: Option<String>) -> Result<(), GitError> {
-    let remote = &remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
+pub fn get_remote_details(state: AppArg) -> Result<Vec<RemoteInfo>, GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        let mut remotes = vec![];
+        for name in repo.repo.remotes()?.iter().flatten() {
+            let remote = repo.repo.find_remote(name)?;
+            remotes.push(RemoteInfo {
+                name: name.to_string(),
+                url: remote.url().map(|url| url.to_string()),
+                push_url: remote.pushurl().map(|url| url.to_string()),
+                fetch_refspecs: remote
+                    .fetch_refspecs()?
+                    .iter()
+                    .flatten()
+                    .map(|refspec| refspec.to_string())
+                    .collect(),
+                push_refspecs: remote
+                    .push_refspecs()?
+                    .iter()
+                    .flatten()
+                    .map(|refspec| refspec.to_string())
+                    .collect(),
+            });
+        }
+        return Ok(remotes);
+    }
+    Err(GitError::RepoNotFound)
+}
 
+#[command]
+pub fn add_remote(
+    state: AppArg,
+    name: String,
+    url: String,
+    fetch_refspec: Option<String>,
+) -> Result<(), GitError> {
     let repo = state.repo.clone();
     let repo = repo.lock().unwrap();
     let repo = repo.as_ref();
     if let Some(repo) = repo {
-        // Figure out whether it's a named remote or a URL
-        let mut cb = RemoteCallbacks::new();
-        let mut remote = repo
-            .repo
-            .find_remote(remote)
-            .or_else(|_| repo.repo.remote_anonymous(remote))?;
-        cb.sideband_progress(|data| {
-            print!("remote: {}", str::from_utf8(data).unwrap());
-            io::stdout().flush().unwrap();
-            true
-        });
-        // This callback gets called for each remote-tracking branch that gets
-        // updated. The message we output depends on whether it's a new one or an
-        // update.
-        cb.update_tips(|refname, a, b| {
-            if a.is_zero() {
-                println!("[new]     {:20} {}", b, refname);
-            } else {
-                println!("[updated] {:10}..{:10} {}", a, b, refname);
-            }
-            true
-        });
-        // Here we show processed and total objects in the pack and the amount of
-        // received data. Most frontends will probably want to show a percentage and
-        // the download rate.
-        cb.transfer_progress(|stats| {
-            if stats.received_objects() == stats.total_objects() {
-                print!(
-                    "Resolving deltas {}/{}\r",
-                    stats.indexed_deltas(),
-                    stats.total_deltas()
-                );
-            } else if stats.total_objects() > 0 {
-                print!(
-                    "Received {}/{} objects ({}) in {} bytes\r",
-                    stats.received_objects(),
-                    stats.total_objects(),
-                    stats.indexed_objects(),
-                    stats.received_bytes()
-                );
-            }
-            io::stdout().flush().unwrap();
-            true
-        });
-        let git_config = git2::Config::open_default().unwrap();
-        let mut ch = git2_credentials::CredentialHandler::new(git_config);
-        cb.credentials(move |url, username, allowed| {
-            ch.try_next_credential(url, username, allowed)
-        });
-        let mut fo = FetchOptions::new();
-        fo.remote_callbacks(cb);
-        remote.download(&[] as &[String], Some(&mut fo))?;
-
-        // Disconnect the underlying connection to prevent from idling.
-        remote.disconnect()?;
-        // Update the references in the remote's namespace to point to the right
-        // commits. This may be needed even if there was no packfile to download,
-        // which can happen e.g. when the branches have been changed but all the
-        // needed objects are available locally.
-        remote.update_tips(None, true, AutotagOption::Unspecified, None)?;
+        match fetch_refspec {
+            Some(fetch_refspec) => repo.repo.remote_with_fetch(&name, &url, &fetch_refspec)?,
+            None => repo.repo.remote(&name, &url)?,
+        };
         return Ok(());
     }
     Err(GitError::RepoNotFound)
 }
 
 #[command]
-pub fn push_remote(state: AppArg, remote: Option<String>) -> Result<(), GitError> {
-    let remote = &remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
-
+pub fn rename_remote(
+    state: AppArg,
+    name: String,
+    new_name: String,
+) -> Result<Vec<String>, GitError> {
     let repo = state.repo.clone();
     let repo = repo.lock().unwrap();
     let repo = repo.as_ref();
     if let Some(repo) = repo {
-        let cb = git::get_remote_callbacks();
-        let mut remote = repo
-            .repo
-            .find_remote(remote)
-            .or_else(|_| repo.repo.remote_anonymous(remote))?;
+        // Refspecs that don't follow the default layout are left for the user to fix
+        let problems = repo.repo.remote_rename(&name, &new_name)?;
+        return Ok(problems
+            .iter()
+            .flatten()
+            .map(|refspec| refspec.to_string())
+            .collect());
+    }
+    Err(GitError::RepoNotFound)
+}
 
-        let head = repo.repo.head()?;
-        let head = head.shorthand().unwrap();
-        let mut conn = remote.connect_auth(git2::Direction::Push, Some(cb), None)?;
-        let mut push_options = PushOptions::new();
-        let refspecs = format!("refs/heads/{}", head.to_string());
-        conn.remote().push(&[refspecs], Some(&mut push_options))?;
-        conn.remote().disconnect()?;
-        conn.remote()
-            .update_tips(None, true, AutotagOption::Unspecified, None)?;
+#[command]
+pub fn remove_remote(state: AppArg, name: String) -> Result<(), GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        repo.repo.remote_delete(&name)?;
         return Ok(());
     }
     Err(GitError::RepoNotFound)
 }
 
 #[command]
-pub fn pull_from_remote(state: AppArg, remote: Option<String>) -> Result<(), GitError> {
-    let remote_name = &remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
+pub fn set_remote_url(state: AppArg, name: String, url: String) -> Result<(), GitError> {
     let repo = state.repo.clone();
     let repo = repo.lock().unwrap();
     let repo = repo.as_ref();
     if let Some(repo) = repo {
-        let remote_branch = repo.get_current_branch_name()?;
-        let mut remote = repo.repo.find_remote(remote_name)?;
-        let fetch_commit = git::do_fetch(&repo.repo, &[&remote_branch], &mut remote)?;
-        git::do_merge(&repo.repo, &remote_branch, fetch_commit)?;
+        repo.repo.remote_set_url(&name, &url)?;
         return Ok(());
     }
     Err(GitError::RepoNotFound)
 }
 
 #[command]
-pub fn get_modified_files(state: AppArg) -> Result<Vec<FileStatus>, GitError> {
+pub fn set_remote_push_url(
+    state: AppArg,
+    name: String,
+    url: Option<String>,
+) -> Result<(), GitError> {
     let repo = state.repo.clone();
     let repo = repo.lock().unwrap();
     let repo = repo.as_ref();
-    let mut status_options = git2::StatusOptions::new();
-
     if let Some(repo) = repo {
+        // Without a push URL pushes go back to the fetch one
+        repo.repo.remote_set_pushurl(&name, url.as_deref())?;
+        return Ok(());
+    }
+    Err(GitError::RepoNotFound)
+}
+
+#[command]
+pub async fn fetch_remote(
+    state: AppArg<'_>,
+    window: Window,
+    remote: Option<String>,
+    all: Option<bool>,
+    prune: Option<bool>,
+    operation_id: Option<u32>,
+) -> Result<Vec<FetchSummary>, GitError> {
+    let remote = remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
+    // Started first so an early error releases the id too
+    let operation = state.operations.start(operation_id);
+    let repo = repo_handle(&state)?;
+    let token = operation.token.clone();
+    run_blocking(move || {
+        let fetch_all = all.unwrap_or(false);
+        let names: Vec<String> = if fetch_all {
+            repo.repo
+                .remotes()?
+                .iter()
+                .flatten()
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            vec![remote]
+        };
+        let mut summaries = vec![];
+        for name in names {
+            let remote = if fetch_all {
+                repo.repo.find_remote(&name)
+            } else {
+                // Figure out whether it's a named remote or a URL
+                repo.repo
+                    .find_remote(&name)
+                    .or_else(|_| repo.repo.remote_anonymous(&name))
+            };
+            let progress =
+                ProgressReporter::new(&window, RemoteOperation::Fetch, &name, token.clone());
+            let fetched = remote
+                .map_err(GitError::from)
+                .and_then(|mut remote| git::fetch_with_summary(&mut remote, prune, &progress));
+            match fetched {
+                Ok(updates) => summaries.push(FetchSummary {
+                    remote: name,
+                    updates,
+                    error: None,
+                }),
+                // One unreachable remote doesn't keep the others from being fetched
+                Err(e) if fetch_all && !matches!(e, GitError::Cancelled) => {
+                    summaries.push(FetchSummary {
+                        remote: name,
+                        updates: vec![],
+                        error: Some(e.to_string()),
+                    })
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(summaries)
+    })
+    .await
+}
+
+#[command]
+pub async fn push_remote(
+    state: AppArg<'_>,
+    window: Window,
+    remote: Option<String>,
+    skip_hooks: Option<bool>,
+    operation_id: Option<u32>,
+) -> Result<(), GitError> {
+    let remote = remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
+    // Only cancellable until the pack starts uploading
+    let operation = state.operations.start(operation_id);
+    let repo = repo_handle(&state)?;
+    let token = operation.token.clone();
+    run_blocking(move || {
+        let mut remote = repo
+            .repo
+            .find_remote(&remote)
+            .or_else(|_| repo.repo.remote_anonymous(&remote))?;
+        let head = repo.repo.head()?;
+        let head = head.shorthand().unwrap();
+        let refspecs = [format!("refs/heads/{0}:refs/heads/{0}", head)];
+
+        let hooks = Hooks::new(&repo.repo, &window, skip_hooks.unwrap_or(false));
+        let progress = ProgressReporter::new(
+            &window,
+            RemoteOperation::Push,
+            remote.name().or_else(|| remote.url()).unwrap_or_default(),
+            token,
+        );
+        git::push_refspecs(&repo.repo, &hooks, &progress, &mut remote, &refspecs)
+    })
+    .await
+}
+
+#[command]
+pub async fn pull_from_remote(
+    state: AppArg<'_>,
+    window: Window,
+    remote: Option<String>,
+    skip_hooks: Option<bool>,
+    operation_id: Option<u32>,
+) -> Result<(), GitError> {
+    let remote_name = remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
+    let operation = state.operations.start(operation_id);
+    let repo = repo_handle(&state)?;
+    let token = operation.token.clone();
+    run_blocking(move || {
+        let remote_branch = repo.get_current_branch_name()?;
+        let mut remote = repo.repo.find_remote(&remote_name)?;
+        let progress = ProgressReporter::new(&window, RemoteOperation::Fetch, &remote_name, token);
+        let fetch_commit = git::do_fetch(&repo.repo, &[&remote_branch], &mut remote, &progress)?;
+        if git::do_merge(&repo.repo, &remote_branch, fetch_commit)? {
+            // The argument tells whether the merge was a squash
+            let hooks = Hooks::new(&repo.repo, &window, skip_hooks.unwrap_or(false));
+            let _ = hooks.run(Hook::PostMerge, &["0"], &[]);
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[command]
+pub async fn get_modified_files(state: AppArg<'_>) -> Result<Vec<FileStatus>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let mut status_options = git2::StatusOptions::new();
         let statuses = repo.repo.statuses(Some(
             status_options
                 .include_ignored(false)
@@ -319,17 +508,15 @@ pub fn get_modified_files(state: AppArg) -> Result<Vec<FileStatus>, GitError> {
                 }
             })
             .collect();
-        return Ok(files_statuses);
-    }
-    Err(GitError::RepoNotFound)
+        Ok(files_statuses)
+    })
+    .await
 }
 
 #[command]
-pub fn get_repo_diff(state: AppArg) -> Result<Stats, GitError> {
-    let repo = state.repo.clone();
-    let repo = repo.lock().unwrap();
-    let repo = repo.as_ref();
-    if let Some(repo) = repo {
+pub async fn get_repo_diff(state: AppArg<'_>) -> Result<Stats, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
         let stats = match repo.repo.diff_index_to_workdir(None, None) {
             Ok(diff) => match diff.stats() {
                 Ok(stats) => stats,
@@ -341,35 +528,71 @@ pub fn get_repo_diff(state: AppArg) -> Result<Stats, GitError> {
                 return Err(GitError::GetDiffFailed);
             }
         };
-        return Ok(Stats {
+        Ok(Stats {
             deletions: stats.deletions(),
             insertions: stats.insertions(),
             files_changed: stats.files_changed(),
-        });
-    }
-    Err(GitError::RepoNotFound)
+        })
+    })
+    .await
 }
 
 #[command]
-pub fn add_all(state: AppArg) -> Result<(), GitError> {
-    let repo = state.repo.clone();
-    let repo = repo.lock().unwrap();
-    let repo = repo.as_ref();
-    if let Some(repo) = repo {
+pub async fn get_staged_file_stats(state: AppArg<'_>) -> Result<Vec<FileStats>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(None, &settings);
+        let diff = diff::staged_diff(&repo, &mut diff_opts, &settings)?;
+        diff::file_stats(&diff)
+    })
+    .await
+}
+
+#[command]
+pub async fn get_unstaged_file_stats(state: AppArg<'_>) -> Result<Vec<FileStats>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(None, &settings);
+        let diff = diff::unstaged_diff(&repo, &mut diff_opts, &settings)?;
+        diff::file_stats(&diff)
+    })
+    .await
+}
+
+#[command]
+pub async fn get_commit_file_stats(
+    state: AppArg<'_>,
+    commit: String,
+) -> Result<Vec<FileStats>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(None, &settings);
+        let diff = diff::commit_diff(&repo, &commit, &mut diff_opts, &settings)?;
+        diff::file_stats(&diff)
+    })
+    .await
+}
+
+#[command]
+pub async fn add_all(state: AppArg<'_>) -> Result<(), GitError> {
+    let repo = repo_handle(&state)?;
+    // Hashing a large working tree takes a while
+    run_blocking(move || {
         let mut index = repo.repo.index()?;
         index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)?;
         index.write()?;
-        return Ok(());
-    }
-    Err(GitError::RepoNotFound)
+        Ok(())
+    })
+    .await
 }
 
 #[command]
-pub fn get_staged_files(state: AppArg) -> Result<Vec<FileStatus>, GitError> {
-    let repo = state.repo.clone();
-    let repo = repo.lock().unwrap();
-    let repo = repo.as_ref();
-    if let Some(repo) = repo {
+pub async fn get_staged_files(state: AppArg<'_>) -> Result<Vec<FileStatus>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
         let mut status_options = git2::StatusOptions::new();
         let files_statuses = repo
             .repo
@@ -395,16 +618,14 @@ pub fn get_staged_files(state: AppArg) -> Result<Vec<FileStatus>, GitError> {
                 }
             })
             .collect();
-        return Ok(files_statuses);
-    }
-    Err(GitError::RepoNotFound)
+        Ok(files_statuses)
+    })
+    .await
 }
 #[command]
-pub fn add(state: AppArg, files: Vec<String>) -> Result<(), GitError> {
-    let repo = state.repo.clone();
-    let repo = repo.lock().unwrap();
-    let repo = repo.as_ref();
-    if let Some(repo) = repo {
+pub async fn add(state: AppArg<'_>, files: Vec<String>) -> Result<(), GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
         let statuses = repo.repo.statuses(None).unwrap();
         let mut files_to_add = vec![];
         for entry in statuses.iter() {
@@ -423,190 +644,574 @@ pub fn add(state: AppArg, files: Vec<String>) -> Result<(), GitError> {
         let mut index = repo.repo.index()?;
         index.add_all(files_to_add.iter(), git2::IndexAddOption::DEFAULT, None)?;
         index.write()?;
-        return Ok(());
+        Ok(())
+    })
+    .await
+}
+
+#[command]
+pub async fn discard(
+    state: AppArg<'_>,
+    files: Vec<String>,
+    source: Option<DiscardSource>,
+    to_trash: Option<bool>,
+) -> Result<(), GitError> {
+    let source = source.unwrap_or(DiscardSource::Index);
+    let to_trash = to_trash.unwrap_or(false);
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        for file in files {
+            repo.discard_file(&file, &source, to_trash)?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[command]
+pub async fn discard_selection(
+    state: AppArg<'_>,
+    file: String,
+    selection: DiscardSelection,
+) -> Result<(), GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || repo.discard_selection(&file, &selection)).await
+}
+
+#[command]
+pub async fn git_diff(state: AppArg<'_>) -> Result<Vec<DiffFile>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let tree = repo.head_tree()?;
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(None, &settings);
+        diff_opts
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        let mut diff = repo
+            .repo
+            .diff_tree_to_workdir_with_index(tree.as_ref(), Some(&mut diff_opts))?;
+        diff::find_similar(&mut diff, &settings)?;
+        diff::diff_files(&diff)
+    })
+    .await
+}
+
+#[command]
+pub async fn git_diff_staged(
+    state: AppArg<'_>,
+    path: Option<String>,
+) -> Result<Vec<DiffFile>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(path.as_deref(), &settings);
+        let diff = diff::staged_diff(&repo, &mut diff_opts, &settings)?;
+        diff::diff_files(&diff)
+    })
+    .await
+}
+
+#[command]
+pub async fn git_diff_unstaged(
+    state: AppArg<'_>,
+    path: Option<String>,
+) -> Result<Vec<DiffFile>, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(path.as_deref(), &settings);
+        let diff = diff::unstaged_diff(&repo, &mut diff_opts, &settings)?;
+        diff::diff_files(&diff)
+    })
+    .await
+}
+
+#[command]
+pub async fn git_diff_file(
+    state: AppArg<'_>,
+    path: String,
+    staged: Option<bool>,
+    max_lines: Option<usize>,
+    highlight: Option<bool>,
+) -> Result<Option<DiffFile>, GitError> {
+    let repo = repo_handle(&state)?;
+    let highlight_cache = state.highlight_cache.clone();
+    run_blocking(move || {
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(Some(&path), &settings);
+        diff_opts.max_size(diff::MAX_FILE_SIZE);
+        let staged = staged.unwrap_or(false);
+        let diff = if staged {
+            diff::staged_diff(&repo, &mut diff_opts, &settings)?
+        } else {
+            diff::unstaged_diff(&repo, &mut diff_opts, &settings)?
+        };
+        if diff.deltas().len() == 0 {
+            return Ok(None);
+        }
+        let max_lines = max_lines.unwrap_or(diff::MAX_FILE_LINES);
+        let mut file = diff::diff_file(&diff, 0, Some(max_lines))?;
+        if highlight.unwrap_or(false) {
+            highlight::highlight_file(&repo, &highlight_cache, &mut file, staged)?;
+        }
+        Ok(Some(file))
+    })
+    .await
+}
+
+#[command]
+pub async fn git_diff_image(
+    state: AppArg<'_>,
+    path: String,
+    staged: Option<bool>,
+) -> Result<ImageDiff, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || image_diff::image_diff(&repo, &path, staged.unwrap_or(false))).await
+}
+
+#[command]
+pub async fn diff_revisions(
+    state: AppArg<'_>,
+    from: String,
+    to: String,
+    range: Option<RevisionRange>,
+    paths: Option<Vec<String>>,
+) -> Result<RevisionDiff, GitError> {
+    let repo = repo_handle(&state)?;
+    run_blocking(move || {
+        let settings = diff::load_settings(&repo)?;
+        let mut diff_opts = diff::diff_options(None, &settings);
+        if let Some(paths) = paths {
+            for path in paths {
+                diff_opts.pathspec(path);
+            }
+            diff_opts.disable_pathspec_match(true);
+        }
+        let range = range.unwrap_or(RevisionRange::TwoDot);
+        let diff = diff::revisions_diff(&repo, &from, &to, &range, &mut diff_opts, &settings)?;
+        let stats = diff.stats()?;
+        Ok(RevisionDiff {
+            stats: Stats {
+                deletions: stats.deletions(),
+                insertions: stats.insertions(),
+                files_changed: stats.files_changed(),
+            },
+            files: diff::diff_files(&diff)?,
+        })
+    })
+    .await
+}
+
+#[command]
+pub fn read_diff_settings(state: AppArg) -> Result<DiffSettings, GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        return diff::load_settings(repo);
     }
     Err(GitError::RepoNotFound)
 }
 
 #[command]
-pub fn discard(state: AppArg, files: Vec<String>) -> Result<(), GitError> {
+pub fn write_diff_settings(state: AppArg, settings: DiffSettings) -> Result<(), GitError> {
     let repo = state.repo.clone();
     let repo = repo.lock().unwrap();
     let repo = repo.as_ref();
     if let Some(repo) = repo {
-        for file in files {
-            let file_abs_path = get_absolute_path_from_relative(repo, &file);
-            let file_path = Path::new(&file_abs_path);
-            let head = repo.repo.head()?;
-
-            // Get the last commit (OID) for the file
-            let target = head.target().ok_or_else(|| GitError::InvalidHead)?;
-
-            // Get the object for the file in the last commit
-            let obj = repo
-                .repo
-                .find_object(target, Some(git2::ObjectType::Commit))?;
+        let db = db::Db::new()?;
+        db.write_diff_settings(&repo.path(), &settings)?;
+        return Ok(());
+    }
+    Err(GitError::RepoNotFound)
+}
 
-            // Get the commit from the object
-            let commit = obj.as_commit().ok_or_else(|| GitError::InvalidCommit)?;
+#[command]
+pub fn read_commit_template(state: AppArg) -> Result<Option<String>, GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        return commit_message::load_template(repo);
+    }
+    Err(GitError::RepoNotFound)
+}
 
-            let tree = commit.tree()?;
-            let entry = tree.get_path(Path::new(&file))?;
-            let blob = repo.repo.find_blob(entry.id())?;
+#[command]
+pub fn write_commit_template(state: AppArg, template: Option<String>) -> Result<(), GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        let db = db::Db::new()?;
+        db.write_commit_template(&repo.path(), template.as_deref())?;
+        return Ok(());
+    }
+    Err(GitError::RepoNotFound)
+}
 
-            // Write the contents of the blob to the file in the working directory
-            let contents = blob.content();
-            fs::write(file_path, contents).unwrap();
+#[command]
+pub fn read_commit_rules(state: AppArg) -> Result<CommitRules, GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        return commit_message::load_rules(repo);
+    }
+    Err(GitError::RepoNotFound)
+}
 
-            // Add the file to the index
-            let mut index = repo.repo.index()?;
-            index.add_path(Path::new(&file))?;
-            index.write()?;
+#[command]
+pub fn write_commit_rules(state: AppArg, rules: CommitRules) -> Result<(), GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        // Refuse a pattern every commit would then fail on
+        if let Some(pattern) = &rules.issue_key_pattern {
+            regex::Regex::new(pattern)?;
         }
-
+        let db = db::Db::new()?;
+        db.write_commit_rules(&repo.path(), &rules)?;
         return Ok(());
     }
     Err(GitError::RepoNotFound)
 }
 
 #[command]
-pub fn git_diff(state: AppArg) -> Result<Vec<GitDiff>, GitError> {
+pub fn validate_commit_message(
+    state: AppArg,
+    message: String,
+) -> Result<Vec<CommitViolation>, GitError> {
     let repo = state.repo.clone();
     let repo = repo.lock().unwrap();
     let repo = repo.as_ref();
     if let Some(repo) = repo {
-        let head = repo.repo.head()?;
+        // Checked the way the commit will clean it up
+        let message = git2::message_prettify(message, Some(b'#'))?;
+        return commit_message::validate(&message, &commit_message::load_rules(repo)?);
+    }
+    Err(GitError::RepoNotFound)
+}
 
-        // Get the last commit (OID) for the file
-        let target = head.target().ok_or_else(|| GitError::InvalidHead)?;
+#[command]
+pub fn read_commit_draft(state: AppArg) -> Result<Option<String>, GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        let db = db::Db::new()?;
+        let draft = db.read_commit_draft(&repo.path(), &repo.get_current_branch_name()?)?;
+        return Ok(draft);
+    }
+    Err(GitError::RepoNotFound)
+}
 
-        // Get the object for the file in the last commit
-        let obj = repo
-            .repo
-            .find_object(target, Some(git2::ObjectType::Commit))?;
+#[command]
+pub fn write_commit_draft(state: AppArg, message: Option<String>) -> Result<(), GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        // An emptied message box leaves no draft behind
+        let message = message.filter(|message| !message.trim().is_empty());
+        let db = db::Db::new()?;
+        db.write_commit_draft(
+            &repo.path(),
+            &repo.get_current_branch_name()?,
+            message.as_deref(),
+        )?;
+        return Ok(());
+    }
+    Err(GitError::RepoNotFound)
+}
 
-        // Get the commit from the object
-        let commit = obj.as_commit().ok_or_else(|| GitError::InvalidCommit)?;
+#[command]
+pub fn search_commit_messages(
+    state: AppArg,
+    query: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<String>, GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        let query = query.unwrap_or_default().to_lowercase();
+        let db = db::Db::new()?;
+        let messages = db
+            .read_commit_messages(&repo.path())?
+            .into_iter()
+            .filter(|message| message.to_lowercase().contains(&query))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+        return Ok(messages);
+    }
+    Err(GitError::RepoNotFound)
+}
 
-        let tree = commit.tree()?;
+#[command]
+pub async fn commit(
+    state: AppArg<'_>,
+    window: Window,
+    message: String,
+    author: Option<Identity>,
+    co_authors: Option<Vec<Identity>>,
+    skip_hooks: Option<bool>,
+) -> Result<(), GitError> {
+    let repo = repo_handle(&state)?;
+    // Hooks can take as long as they like
+    run_blocking(move || {
+        let co_authors = co_authors.unwrap_or_default();
+        let message = commit_message::add_co_authors(&message, &co_authors);
+        // Opened before committing, a store that can't be opened then stops
+        // the commit instead of losing what it records
+        let db = db::Db::new()?;
+        let rules = commit_message::load_rules(&repo)?;
+        let branch = repo.get_current_branch_name()?;
+        let hooks = Hooks::new(&repo.repo, &window, skip_hooks.unwrap_or(false));
+        hooks.run(Hook::PreCommit, &[], &[])?;
+        // The message hooks edit the message in place, like when committing with -m
+        let message_path = repo.repo.path().join("COMMIT_EDITMSG");
+        fs::write(&message_path, &message)?;
+        let message_arg = message_path.to_string_lossy();
+        hooks.run(Hook::PrepareCommitMsg, &[&message_arg, "message"], &[])?;
+        hooks.run(Hook::CommitMsg, &[&message_arg], &[])?;
+        // Cleaned up like git does, comments and trailing blank lines dropped
+        let message = git2::message_prettify(fs::read_to_string(&message_path)?, Some(b'#'))?;
+        if message.is_empty() {
+            return Err(GitError::Error("The commit message is empty".to_string()));
+        }
+        // Checked as the hooks left it, that's the message committed
+        let violations = commit_message::validate(&message, &rules)?;
+        if !violations.is_empty() {
+            return Err(GitError::InvalidCommitMessage(violations));
+        }
 
-        let mut status_options = git2::StatusOptions::new();
-        let mut diff_opts = git2::DiffOptions::new();
+        let mut index = repo.repo.index()?;
+        // pre-commit may have staged more changes
+        index.read(false)?;
+        let oid = index.write_tree()?;
+        let tree = repo.repo.find_tree(oid)?;
+        // A repository without commits gets a root commit
+        let parent = repo.head_commit()?;
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let committer = repo.repo.signature()?;
+        let author_signature = match &author {
+            Some(author) => Signature::now(&author.name, &author.email)?,
+            None => committer.clone(),
+        };
+        let commit_id =
+            repo.create_commit(&author_signature, &committer, &message, &tree, &parents)?;
+        let summary = message.lines().next().unwrap_or_default();
+        let reflog_msg = if parent.is_some() {
+            format!("commit: {}", summary)
+        } else {
+            format!("commit (initial): {}", summary)
+        };
+        repo.update_head(commit_id, &reflog_msg)?;
+        // The commit is done, a failing post-commit can't undo it
+        let _ = hooks.run(Hook::PostCommit, &[], &[]);
 
-        let mut files_paths = vec![];
-        repo.repo
-            .statuses(Some(
-                status_options
-                    .include_ignored(false)
-                    .include_untracked(true),
-            ))?
-            .iter()
-            .for_each(|entry| {
-                let head = entry.head_to_index();
-                let status = entry.status();
-                let path = entry.path().unwrap();
-                if !path_is_file(&path) {
-                    return;
-                }
-                if head.is_some() && status.intersects(git::INTERESTING_STAGED) {
-                    let head = head.unwrap();
-                    if head.new_file().size() != 0 {
-                        diff_opts.pathspec(Path::new(&path.to_owned()));
-                    }
-                } else {
-                    if status.intersects(git::INTERESTING) {
-                        diff_opts.pathspec(Path::new(&path.to_owned()));
-                        files_paths.push(path.to_owned());
-                    }
-                }
-            });
-        let diff = repo
-            .repo
-            .diff_tree_to_workdir_with_index(Some(&tree), Some(&mut diff_opts))?;
+        // Losing what's remembered isn't worth failing a commit already made
+        let credited: Vec<Identity> = author.into_iter().chain(co_authors).collect();
+        if !credited.is_empty() {
+            let _ = db.add_recent_identities(&credited);
+        }
+        let _ = db.add_commit_message(&repo.path(), &message);
+        let _ = db.write_commit_draft(&repo.path(), &branch, None);
+        Ok(())
+    })
+    .await
+}
 
-        let mut lines: Vec<GitDiff> = Vec::new();
+#[command]
+pub async fn verify_commit_signatures(
+    state: AppArg<'_>,
+    commits: Vec<String>,
+) -> Result<Vec<CommitSignature>, GitError> {
+    let repo = repo_handle(&state)?;
+    // Every signature is checked by running gpg or ssh-keygen
+    run_blocking(move || {
+        let mut signatures = Vec::with_capacity(commits.len());
+        for commit in commits {
+            let oid = repo.repo.revparse_single(&commit)?.peel_to_commit()?.id();
+            signatures.push(signing::verify_commit(&repo.repo, oid)?);
+        }
+        Ok(signatures)
+    })
+    .await
+}
 
-        diff.print(git2::DiffFormat::Patch, |_, _, line_diff| {
-            let line = str::from_utf8(line_diff.content()).unwrap().to_owned();
-            lines.push(GitDiff {
-                diff_content: line,
-                new_line: line_diff.new_lineno(),
-                old_line: line_diff.old_lineno(),
-                origin: line_diff.origin(),
-            });
-            if line_diff.origin() == 'F' {
-                let line = str::from_utf8(line_diff.content()).unwrap().to_owned();
-                let (origin_name, current_name) = get_origin_and_current_name_from_line(&line);
-                files_paths = files_paths
-                    .iter()
-                    .filter(|x| x != &&origin_name && x != &&current_name)
-                    .map(|x| x.to_owned())
-                    .collect();
-            }
-            return true;
-        })?;
-
-        for file_path in files_paths {
-            let file_abs_path = get_absolute_path_from_relative(repo, &file_path);
-            let file = fs::read_to_string(Path::new(&file_abs_path)).unwrap();
-            lines.push(GitDiff {
-                diff_content: format!("diff --git a/{} b/{}", file_path, file_path),
-                new_line: None,
-                old_line: None,
-                origin: 'F',
+#[command]
+pub fn list_tags(state: AppArg) -> Result<Vec<TagInfo>, GitError> {
+    let repo = state.repo.clone();
+    let repo = repo.lock().unwrap();
+    let repo = repo.as_ref();
+    if let Some(repo) = repo {
+        let mut tags = vec![];
+        for name in repo.repo.tag_names(None)?.iter().flatten() {
+            let reference = repo.repo.find_reference(&format!("refs/tags/{}", name))?;
+            // Tags can point at trees and blobs too, or at objects missing
+            // from a broken repository
+            let (target, target_kind) = match reference.peel(ObjectType::Any) {
+                Ok(object) => (
+                    object.id().to_string(),
+                    object.kind().map(|kind| kind.str().to_string()),
+                ),
+                Err(_) => (
+                    reference
+                        .target()
+                        .map(|oid| oid.to_string())
+                        .unwrap_or_default(),
+                    None,
+                ),
+            };
+            // Lightweight tags point right at the commit, without a tag object
+            let tag = match reference.target() {
+                Some(oid) => repo.repo.find_tag(oid).ok(),
+                None => None,
+            };
+            tags.push(match tag {
+                Some(tag) => TagInfo {
+                    name: name.to_string(),
+                    target,
+                    target_kind,
+                    annotated: true,
+                    tagger: tag.tagger().map(|tagger| Identity {
+                        name: tagger.name().unwrap_or_default().to_string(),
+                        email: tagger.email().unwrap_or_default().to_string(),
+                    }),
+                    time: tag.tagger().map(|tagger| tagger.when().seconds()),
+                    message: tag.message().map(|message| message.to_string()),
+                },
+                None => TagInfo {
+                    name: name.to_string(),
+                    target,
+                    target_kind,
+                    annotated: false,
+                    tagger: None,
+                    time: None,
+                    message: None,
+                },
             });
-
-            for (idx, line) in file.lines().enumerate() {
-                lines.push(GitDiff {
-                    diff_content: format!("{}\n", line.to_owned()),
-                    new_line: Some(idx as u32 + 1),
-                    old_line: Some(idx as u32 + 1),
-                    origin: '+',
-                });
-            }
         }
-        return Ok(lines);
+        return Ok(tags);
     }
     Err(GitError::RepoNotFound)
 }
 
 #[command]
-pub fn commit(state: AppArg, message: String) -> Result<(), GitError> {
+pub async fn create_tag(
+    state: AppArg<'_>,
+    name: String,
+    target: Option<String>,
+    message: Option<String>,
+    sign: Option<bool>,
+    force: Option<bool>,
+) -> Result<(), GitError> {
+    let repo = repo_handle(&state)?;
+    // Signing can wait on a passphrase prompt
+    run_blocking(move || {
+        let target = repo
+            .repo
+            .revparse_single(target.as_deref().unwrap_or("HEAD"))?;
+        let force = force.unwrap_or(false);
+        let sign = match sign {
+            Some(sign) => sign,
+            None => message.is_some() && signing::should_sign_tags(&repo.repo.config()?),
+        };
+        // A signature needs a tag object to live in, even without a message
+        match (message, sign) {
+            (None, false) => {
+                repo.repo.tag_lightweight(&name, &target, force)?;
+            }
+            (message, sign) => {
+                let message = message.unwrap_or_default();
+                repo.create_annotated_tag(&name, &target, &message, sign, force)?;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[command]
+pub fn delete_tag(state: AppArg, name: String) -> Result<(), GitError> {
     let repo = state.repo.clone();
     let repo = repo.lock().unwrap();
     let repo = repo.as_ref();
     if let Some(repo) = repo {
-        let mut index = repo.repo.index().unwrap();
-        let oid = index.write_tree()?;
-        let tree = repo.repo.find_tree(oid)?;
-        let parent = repo.repo.head()?.peel_to_commit()?;
-        let parent_id = parent.id();
-        let parent_id = repo.repo.find_commit(parent_id)?;
-        let commit = repo.repo.commit_create_buffer(
-            &repo.repo.signature().unwrap(),
-            &repo.repo.signature().unwrap(),
-            &message,
-            &tree,
-            &[&parent_id],
-        )?;
-        let commit_signed = repo.repo.commit_signed(
-            &str::from_utf8(&commit).unwrap().to_string(),
-            &repo.repo.signature().unwrap().to_string(),
-            None,
-        )?;
-        let commit_id = repo.repo.find_commit(commit_signed)?;
-        let head = repo.repo.head()?;
-        let head_id = head.peel_to_commit()?.id();
-        if head_id == commit_id.id() {
-            return Ok(());
-        }
-        let mut head_ref = repo.repo.head().unwrap();
-        head_ref.set_target(commit_id.id(), "commit")?;
+        repo.repo.tag_delete(&name)?;
         return Ok(());
     }
     Err(GitError::RepoNotFound)
 }
 
+#[command]
+pub async fn delete_remote_tag(
+    state: AppArg<'_>,
+    window: Window,
+    name: String,
+    remote: Option<String>,
+    skip_hooks: Option<bool>,
+    operation_id: Option<u32>,
+) -> Result<(), GitError> {
+    let remote_name = remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
+    let operation = state.operations.start(operation_id);
+    let repo = repo_handle(&state)?;
+    let token = operation.token.clone();
+    run_blocking(move || {
+        let mut remote = repo.repo.find_remote(&remote_name)?;
+        let hooks = Hooks::new(&repo.repo, &window, skip_hooks.unwrap_or(false));
+        let progress = ProgressReporter::new(&window, RemoteOperation::Push, &remote_name, token);
+        let refspecs = [format!(":refs/tags/{}", name)];
+        git::push_refspecs(&repo.repo, &hooks, &progress, &mut remote, &refspecs)
+    })
+    .await
+}
+
+#[command]
+pub async fn push_tags(
+    state: AppArg<'_>,
+    window: Window,
+    remote: Option<String>,
+    tag: Option<String>,
+    skip_hooks: Option<bool>,
+    operation_id: Option<u32>,
+) -> Result<(), GitError> {
+    let remote_name = remote.unwrap_or(git::DEFAULT_REMOTE.to_string());
+    let operation = state.operations.start(operation_id);
+    let repo = repo_handle(&state)?;
+    let token = operation.token.clone();
+    run_blocking(move || {
+        // Every local tag when no tag is given
+        let names: Vec<String> = match tag {
+            Some(tag) => vec![tag],
+            None => repo
+                .repo
+                .tag_names(None)?
+                .iter()
+                .flatten()
+                .map(|name| name.to_string())
+                .collect(),
+        };
+        if names.is_empty() {
+            return Ok(());
+        }
+        let refspecs: Vec<String> = names
+            .iter()
+            .map(|name| format!("refs/tags/{0}:refs/tags/{0}", name))
+            .collect();
+        let mut remote = repo.repo.find_remote(&remote_name)?;
+        let hooks = Hooks::new(&repo.repo, &window, skip_hooks.unwrap_or(false));
+        let progress = ProgressReporter::new(&window, RemoteOperation::Push, &remote_name, token);
+        git::push_refspecs(&repo.repo, &hooks, &progress, &mut remote, &refspecs)
+    })
+    .await
+}
+
 #[command]
 pub fn get_pending_commits_to_push(state: AppArg) -> Result<u32, GitError> {
     let repo = state.repo.clone();
@@ -671,6 +1276,13 @@ pub fn get_all_repos() -> Result<Vec<db::Repo>, SledError> {
     return Ok(res);
 }
 
+#[command]
+pub fn read_recent_identities() -> Result<Vec<Identity>, SledError> {
+    let db = db::Db::new()?;
+    let res = db.read_recent_identities()?;
+    return Ok(res);
+}
+
 #[command]
 pub fn write_last_opened_repo(repo: Option<String>) -> Result<(), SledError> {
     let db = db::Db::new()?;
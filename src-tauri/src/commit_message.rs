@@ -0,0 +1,304 @@
+use crate::db;
+use crate::error::GitError;
+use crate::git::Repo;
+use crate::state::{CommitRule, CommitRules, CommitViolation, Identity};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fs;
+
+const CO_AUTHOR_TRAILER: &str = "Co-authored-by";
+
+static CONVENTIONAL_SUBJECT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?P<type>[A-Za-z]+)(?:\((?P<scope>[^()]+)\))?!?: \S").unwrap());
+
+/// Rules of the repository from the `Db`, no rule at all when none were saved.
+pub fn load_rules(repo: &Repo) -> Result<CommitRules, GitError> {
+    Ok(db::Db::new()?.read_commit_rules(&repo.path())?)
+}
+
+/// The template saved for the repository, or else the file `commit.template`
+/// points to.
+pub fn load_template(repo: &Repo) -> Result<Option<String>, GitError> {
+    if let Some(template) = db::Db::new()?.read_commit_template(&repo.path())? {
+        return Ok(Some(template));
+    }
+    let path = match repo.repo.config()?.get_path("commit.template") {
+        Ok(path) => path,
+        Err(_) => return Ok(None),
+    };
+    // A relative path is relative to the root of the working tree
+    let path = match repo.repo.workdir() {
+        Some(workdir) => workdir.join(path),
+        None => path,
+    };
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+/// Every rule `message` breaks, in the order of the lines breaking them.
+pub fn validate(message: &str, rules: &CommitRules) -> Result<Vec<CommitViolation>, GitError> {
+    let mut violations = vec![];
+    let mut lines = message.lines();
+    let subject = lines.next().unwrap_or_default();
+
+    if rules.conventional {
+        check_conventional(subject, rules, &mut violations);
+    }
+    if let Some(max_length) = rules.max_subject_length {
+        let length = subject.chars().count();
+        if length > max_length {
+            violations.push(CommitViolation {
+                rule: CommitRule::SubjectLength,
+                line: 1,
+                message: format!(
+                    "The subject has {} characters, the limit is {}",
+                    length, max_length
+                ),
+            });
+        }
+    }
+    if let (true, Some(second_line)) = (rules.blank_second_line, lines.next()) {
+        if !second_line.trim().is_empty() {
+            violations.push(CommitViolation {
+                rule: CommitRule::BlankSecondLine,
+                line: 2,
+                message: "The subject must be followed by a blank line".to_string(),
+            });
+        }
+    }
+    if let Some(pattern) = &rules.issue_key_pattern {
+        if !Regex::new(pattern)?.is_match(message) {
+            violations.push(CommitViolation {
+                rule: CommitRule::IssueKey,
+                line: 1,
+                message: format!("No issue key matching {}", pattern),
+            });
+        }
+    }
+    Ok(violations)
+}
+
+fn check_conventional(subject: &str, rules: &CommitRules, violations: &mut Vec<CommitViolation>) {
+    let captures = match CONVENTIONAL_SUBJECT.captures(subject) {
+        Some(captures) => captures,
+        None => {
+            violations.push(CommitViolation {
+                rule: CommitRule::Conventional,
+                line: 1,
+                message: "The subject must read type(scope): description".to_string(),
+            });
+            return;
+        }
+    };
+    let commit_type = &captures["type"];
+    if !rules.types.is_empty()
+        && !rules
+            .types
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(commit_type))
+    {
+        violations.push(CommitViolation {
+            rule: CommitRule::Type,
+            line: 1,
+            message: format!(
+                "{} isn't one of the types {}",
+                commit_type,
+                rules.types.join(", ")
+            ),
+        });
+    }
+    if let Some(scope) = captures.name("scope") {
+        if !rules.scopes.is_empty() && !rules.scopes.iter().any(|s| s == scope.as_str()) {
+            violations.push(CommitViolation {
+                rule: CommitRule::Scope,
+                line: 1,
+                message: format!(
+                    "{} isn't one of the scopes {}",
+                    scope.as_str(),
+                    rules.scopes.join(", ")
+                ),
+            });
+        }
+    }
+}
+
+/// Appends a `Co-authored-by` trailer for every co-author the message doesn't
+/// credit yet, joining the trailers already at the end of the message.
+pub fn add_co_authors(message: &str, co_authors: &[Identity]) -> String {
+    let message = message.trim_end();
+    let trailers: Vec<String> = co_authors
+        .iter()
+        .map(|co_author| format!("{}: {}", CO_AUTHOR_TRAILER, co_author))
+        .filter(|trailer| {
+            !message
+                .lines()
+                .any(|line| line.trim().eq_ignore_ascii_case(trailer))
+        })
+        .collect();
+    if trailers.is_empty() {
+        return message.to_string();
+    }
+    let separator = if ends_with_trailers(message) {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    format!("{}{}{}\n", message, separator, trailers.join("\n"))
+}
+
+/// Whether the last paragraph, which can't be the subject, is only made of
+/// `Token: value` lines.
+fn ends_with_trailers(message: &str) -> bool {
+    let last_paragraph = match message.rfind("\n\n") {
+        Some(idx) => &message[idx + 2..],
+        None => return false,
+    };
+    last_paragraph
+        .lines()
+        .all(|line| match line.split_once(": ") {
+            Some((token, _)) => {
+                !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            }
+            None => false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(name: &str, email: &str) -> Identity {
+        Identity {
+            name: name.to_string(),
+            email: email.to_string(),
+        }
+    }
+
+    /// Rule and line of every violation.
+    fn broken(message: &str, rules: &CommitRules) -> Vec<(CommitRule, usize)> {
+        validate(message, rules)
+            .unwrap()
+            .into_iter()
+            .map(|violation| (violation.rule, violation.line))
+            .collect()
+    }
+
+    #[test]
+    fn checks_conventional_subject() {
+        let rules = CommitRules {
+            conventional: true,
+            ..CommitRules::default()
+        };
+        for subject in [
+            "feat: add",
+            "feat(ui): add",
+            "feat!: drop",
+            "feat(ui)!: drop",
+        ] {
+            assert_eq!(broken(subject, &rules), vec![], "{}", subject);
+        }
+        for subject in ["add things", "feat:add", "feat(): add", "feat(ui) add"] {
+            assert_eq!(
+                broken(subject, &rules),
+                vec![(CommitRule::Conventional, 1)],
+                "{}",
+                subject
+            );
+        }
+    }
+
+    #[test]
+    fn checks_allowed_types_and_scopes() {
+        let rules = CommitRules {
+            conventional: true,
+            scopes: vec!["ui".to_string()],
+            ..CommitRules::default()
+        };
+        assert_eq!(broken("FIX(ui): x", &rules), vec![]);
+        assert_eq!(broken("fix: x", &rules), vec![]);
+        assert_eq!(broken("wip(ui): x", &rules), vec![(CommitRule::Type, 1)]);
+        assert_eq!(broken("fix(api): x", &rules), vec![(CommitRule::Scope, 1)]);
+
+        let any = CommitRules {
+            conventional: true,
+            types: vec![],
+            ..CommitRules::default()
+        };
+        assert_eq!(broken("wip(api): x", &any), vec![]);
+    }
+
+    #[test]
+    fn counts_subject_length_in_characters() {
+        let rules = CommitRules {
+            max_subject_length: Some(5),
+            ..CommitRules::default()
+        };
+        assert_eq!(broken("ééééé\n\nlonger body", &rules), vec![]);
+        assert_eq!(
+            broken("éééééé", &rules),
+            vec![(CommitRule::SubjectLength, 1)]
+        );
+    }
+
+    #[test]
+    fn checks_blank_second_line() {
+        let rules = CommitRules {
+            blank_second_line: true,
+            ..CommitRules::default()
+        };
+        assert_eq!(broken("subject", &rules), vec![]);
+        assert_eq!(broken("subject\n\nbody", &rules), vec![]);
+        assert_eq!(
+            broken("subject\nbody", &rules),
+            vec![(CommitRule::BlankSecondLine, 2)]
+        );
+    }
+
+    #[test]
+    fn checks_issue_key_anywhere() {
+        let rules = CommitRules {
+            issue_key_pattern: Some("[A-Z]+-[0-9]+".to_string()),
+            ..CommitRules::default()
+        };
+        assert_eq!(broken("fix\n\nRefs ABC-12", &rules), vec![]);
+        assert_eq!(broken("fix", &rules), vec![(CommitRule::IssueKey, 1)]);
+
+        let invalid = CommitRules {
+            issue_key_pattern: Some("[".to_string()),
+            ..CommitRules::default()
+        };
+        assert!(validate("fix", &invalid).is_err());
+    }
+
+    #[test]
+    fn separates_co_authors_from_subject() {
+        assert_eq!(
+            add_co_authors("Fix it\n", &[identity("Ann", "ann@x.org")]),
+            "Fix it\n\nCo-authored-by: Ann <ann@x.org>\n"
+        );
+    }
+
+    #[test]
+    fn joins_trailers_ending_message() {
+        let message = "Fix it\n\nBody\n\nSigned-off-by: Bob <bob@x.org>\n";
+        assert_eq!(
+            add_co_authors(message, &[identity("Ann", "ann@x.org")]),
+            "Fix it\n\nBody\n\nSigned-off-by: Bob <bob@x.org>\nCo-authored-by: Ann <ann@x.org>\n"
+        );
+    }
+
+    #[test]
+    fn skips_co_author_already_credited() {
+        let message = "Fix it\n\nco-authored-by: ann <ANN@x.org>\n";
+        assert_eq!(
+            add_co_authors(
+                message,
+                &[identity("Ann", "ann@x.org"), identity("Bob", "bob@x.org")]
+            ),
+            "Fix it\n\nco-authored-by: ann <ANN@x.org>\nCo-authored-by: Bob <bob@x.org>\n"
+        );
+        assert_eq!(
+            add_co_authors(message, &[identity("Ann", "ann@x.org")]).trim_end(),
+            message.trim_end()
+        );
+    }
+}
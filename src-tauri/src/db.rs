@@ -1,7 +1,14 @@
 use crate::error::SledError;
+use crate::state::{CommitRules, DiffSettings, Identity};
+use once_cell::sync::OnceCell;
 use serde::Serialize;
 use sled;
 
+/// Sled locks its directory while open, every `Db` shares the handle opened
+/// first instead of failing to open another one.
+static SLED: OnceCell<sled::Db> = OnceCell::new();
+
+#[derive(Clone)]
 pub struct Db {
     db: sled::Db,
 }
@@ -14,17 +21,27 @@ pub struct Repo {
 const TMP_PATH: &str = "tmp/db";
 const LAST_OPENED_REPO: &str = "last_opened_repo";
 const THEME: &str = "theme";
+const DIFF_SETTINGS: &str = "diff_settings";
+const RECENT_IDENTITIES: &str = "recent_identities";
+const MAX_RECENT_IDENTITIES: usize = 20;
+const COMMIT_TEMPLATES: &str = "commit_templates";
+const COMMIT_RULES: &str = "commit_rules";
+const COMMIT_DRAFTS: &str = "commit_drafts";
+const COMMIT_MESSAGES: &str = "commit_messages";
+const MAX_COMMIT_MESSAGES: usize = 100;
 
 impl Db {
     pub fn new() -> Result<Self, SledError> {
-        let dir = std::env::current_dir().unwrap();
-        let dir = dir.to_str().unwrap();
-        let mut dir: Vec<&str> = dir.split("\\").collect();
-        dir.pop().unwrap();
-        let dir = dir.join("/");
-        let dir = format!("{}/{}", dir, TMP_PATH);
-        let db = sled::open(dir)?;
-        Ok(Db { db })
+        let db = SLED.get_or_try_init(|| {
+            let dir = std::env::current_dir().unwrap();
+            let dir = dir.to_str().unwrap();
+            let mut dir: Vec<&str> = dir.split("\\").collect();
+            dir.pop().unwrap();
+            let dir = dir.join("/");
+            let dir = format!("{}/{}", dir, TMP_PATH);
+            sled::open(dir)
+        })?;
+        Ok(Db { db: db.clone() })
     }
     pub fn write_last_opened_repo(&self, repo: &str) -> Result<(), SledError> {
         let key = LAST_OPENED_REPO;
@@ -45,6 +62,138 @@ impl Db {
         Ok(value)
     }
 
+    pub fn write_diff_settings(
+        &self,
+        repo_path: &str,
+        settings: &DiffSettings,
+    ) -> Result<(), SledError> {
+        let tree = self.db.open_tree(DIFF_SETTINGS)?;
+        tree.insert(repo_path, serde_json::to_vec(settings)?)?;
+        Ok(())
+    }
+    pub fn read_diff_settings(&self, repo_path: &str) -> Result<DiffSettings, SledError> {
+        let tree = self.db.open_tree(DIFF_SETTINGS)?;
+        if let Some(val) = tree.get(repo_path)? {
+            return Ok(serde_json::from_slice(&val)?);
+        }
+        Ok(DiffSettings::default())
+    }
+
+    /// Moves `identities` to the front of the recent ones, an email is only
+    /// kept once with the name it was last used with.
+    pub fn add_recent_identities(&self, identities: &[Identity]) -> Result<(), SledError> {
+        let mut recent: Vec<Identity> = vec![];
+        for identity in identities
+            .iter()
+            .cloned()
+            .chain(self.read_recent_identities()?)
+        {
+            if !recent
+                .iter()
+                .any(|known| known.email.eq_ignore_ascii_case(&identity.email))
+            {
+                recent.push(identity);
+            }
+        }
+        recent.truncate(MAX_RECENT_IDENTITIES);
+        let tree = self.db.open_tree(RECENT_IDENTITIES)?;
+        tree.insert(RECENT_IDENTITIES, serde_json::to_vec(&recent)?)?;
+        Ok(())
+    }
+    pub fn read_recent_identities(&self) -> Result<Vec<Identity>, SledError> {
+        let tree = self.db.open_tree(RECENT_IDENTITIES)?;
+        if let Some(val) = tree.get(RECENT_IDENTITIES)? {
+            return Ok(serde_json::from_slice(&val)?);
+        }
+        Ok(vec![])
+    }
+
+    /// Stores the template of a repository, or removes it when `None`.
+    pub fn write_commit_template(
+        &self,
+        repo_path: &str,
+        template: Option<&str>,
+    ) -> Result<(), SledError> {
+        let tree = self.db.open_tree(COMMIT_TEMPLATES)?;
+        match template {
+            Some(template) => tree.insert(repo_path, template)?,
+            None => tree.remove(repo_path)?,
+        };
+        Ok(())
+    }
+    pub fn read_commit_template(&self, repo_path: &str) -> Result<Option<String>, SledError> {
+        let tree = self.db.open_tree(COMMIT_TEMPLATES)?;
+        if let Some(val) = tree.get(repo_path)? {
+            return Ok(Some(String::from_utf8_lossy(&val).into_owned()));
+        }
+        Ok(None)
+    }
+
+    pub fn write_commit_rules(
+        &self,
+        repo_path: &str,
+        rules: &CommitRules,
+    ) -> Result<(), SledError> {
+        let tree = self.db.open_tree(COMMIT_RULES)?;
+        tree.insert(repo_path, serde_json::to_vec(rules)?)?;
+        Ok(())
+    }
+    pub fn read_commit_rules(&self, repo_path: &str) -> Result<CommitRules, SledError> {
+        let tree = self.db.open_tree(COMMIT_RULES)?;
+        if let Some(val) = tree.get(repo_path)? {
+            return Ok(serde_json::from_slice(&val)?);
+        }
+        Ok(CommitRules::default())
+    }
+
+    /// Stores the message being written on a branch, or removes it when
+    /// `None`.
+    pub fn write_commit_draft(
+        &self,
+        repo_path: &str,
+        branch: &str,
+        message: Option<&str>,
+    ) -> Result<(), SledError> {
+        let tree = self.db.open_tree(COMMIT_DRAFTS)?;
+        let key = draft_key(repo_path, branch);
+        match message {
+            Some(message) => tree.insert(key, message)?,
+            None => tree.remove(key)?,
+        };
+        Ok(())
+    }
+    pub fn read_commit_draft(
+        &self,
+        repo_path: &str,
+        branch: &str,
+    ) -> Result<Option<String>, SledError> {
+        let tree = self.db.open_tree(COMMIT_DRAFTS)?;
+        if let Some(val) = tree.get(draft_key(repo_path, branch))? {
+            return Ok(Some(String::from_utf8_lossy(&val).into_owned()));
+        }
+        Ok(None)
+    }
+
+    /// Puts `message` first in the history of the repository, reusing a
+    /// message moves it back to the top.
+    pub fn add_commit_message(&self, repo_path: &str, message: &str) -> Result<(), SledError> {
+        let mut messages = self.read_commit_messages(repo_path)?;
+        messages.retain(|known| known != message);
+        messages.insert(0, message.to_string());
+        messages.truncate(MAX_COMMIT_MESSAGES);
+        let tree = self.db.open_tree(COMMIT_MESSAGES)?;
+        tree.insert(repo_path, serde_json::to_vec(&messages)?)?;
+        Ok(())
+    }
+    /// Messages committed in the repository, the most recent first.
+    pub fn read_commit_messages(&self, repo_path: &str) -> Result<Vec<String>, SledError> {
+        let tree = self.db.open_tree(COMMIT_MESSAGES)?;
+        if let Some(val) = tree.get(repo_path)? {
+            return Ok(serde_json::from_slice(&val)?);
+        }
+        Ok(vec![])
+    }
+
     pub fn get(&self, key: &str) -> Result<String, SledError> {
         if let Some(val) = self.db.get(key)? {
             let res = String::from_utf8(val.to_vec()).unwrap();
@@ -90,3 +239,8 @@ impl Db {
         Ok(())
     }
 }
+
+/// Branch names can't hold a NUL byte, so it can't be mistaken for part of one.
+fn draft_key(repo_path: &str, branch: &str) -> String {
+    format!("{}\0{}", repo_path, branch)
+}
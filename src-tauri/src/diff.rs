@@ -0,0 +1,300 @@
+use crate::db;
+use crate::error::GitError;
+use crate::git::Repo;
+use crate::image_diff;
+use crate::state::{
+    DiffAlgorithm, DiffFile, DiffHunk, DiffLine, DiffSettings, FileStats, RevisionRange,
+    WhitespaceMode,
+};
+use crate::word_diff;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use git2::{Diff, DiffDelta, DiffFindOptions, DiffOptions, Patch};
+use std::path::Path;
+use std::str;
+
+/// Files bigger than this are reported as binary instead of being diffed.
+pub const MAX_FILE_SIZE: i64 = 5 * 1024 * 1024;
+/// Lines returned for a single file before its diff gets truncated.
+pub const MAX_FILE_LINES: usize = 5000;
+/// Bytes of content looked at to guess the encoding of a file.
+const ENCODING_SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Diff settings of the repository, the defaults when none were saved yet.
+pub fn load_settings(repo: &Repo) -> Result<DiffSettings, GitError> {
+    Ok(db::Db::new()?.read_diff_settings(&repo.path())?)
+}
+
+/// Diff options shared by every diff command, optionally limited to one path.
+pub fn diff_options(path: Option<&str>, settings: &DiffSettings) -> DiffOptions {
+    let mut diff_opts = DiffOptions::new();
+    if let Some(path) = path {
+        diff_opts.pathspec(path).disable_pathspec_match(true);
+    }
+    diff_opts
+        .context_lines(settings.context_lines)
+        .ignore_whitespace(matches!(settings.whitespace, WhitespaceMode::IgnoreAll))
+        .ignore_whitespace_change(matches!(settings.whitespace, WhitespaceMode::IgnoreChange))
+        .ignore_whitespace_eol(matches!(settings.whitespace, WhitespaceMode::IgnoreEol))
+        .minimal(matches!(settings.algorithm, DiffAlgorithm::Minimal))
+        .patience(matches!(settings.algorithm, DiffAlgorithm::Patience));
+    diff_opts
+}
+
+/// Pairs added and deleted files into renames and copies when enabled.
+pub fn find_similar(diff: &mut Diff, settings: &DiffSettings) -> Result<(), GitError> {
+    if !settings.detect_renames && !settings.detect_copies {
+        return Ok(());
+    }
+    let mut find_opts = DiffFindOptions::new();
+    find_opts
+        .renames(settings.detect_renames)
+        .rename_threshold(settings.similarity_threshold)
+        .copies(settings.detect_copies)
+        .copy_threshold(settings.similarity_threshold)
+        .for_untracked(true);
+    diff.find_similar(Some(&mut find_opts))?;
+    Ok(())
+}
+
+/// Changes staged for the next commit, HEAD to index.
+pub fn staged_diff<'a>(
+    repo: &'a Repo,
+    diff_opts: &mut DiffOptions,
+    settings: &DiffSettings,
+) -> Result<Diff<'a>, GitError> {
+    let tree = repo.head_tree()?;
+    let mut diff = repo
+        .repo
+        .diff_tree_to_index(tree.as_ref(), None, Some(diff_opts))?;
+    find_similar(&mut diff, settings)?;
+    Ok(diff)
+}
+
+/// Changes not staged yet, index to working tree, including untracked files.
+pub fn unstaged_diff<'a>(
+    repo: &'a Repo,
+    diff_opts: &mut DiffOptions,
+    settings: &DiffSettings,
+) -> Result<Diff<'a>, GitError> {
+    diff_opts
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+    let mut diff = repo.repo.diff_index_to_workdir(None, Some(diff_opts))?;
+    find_similar(&mut diff, settings)?;
+    Ok(diff)
+}
+
+/// Changes between two revisions, from `from` (or its merge base with `to` in
+/// three-dot mode) to `to`.
+pub fn revisions_diff<'a>(
+    repo: &'a Repo,
+    from: &str,
+    to: &str,
+    range: &RevisionRange,
+    diff_opts: &mut DiffOptions,
+    settings: &DiffSettings,
+) -> Result<Diff<'a>, GitError> {
+    let from_commit = repo.repo.revparse_single(from)?.peel_to_commit()?;
+    let to_commit = repo.repo.revparse_single(to)?.peel_to_commit()?;
+    let base_commit = match range {
+        RevisionRange::TwoDot => from_commit,
+        RevisionRange::ThreeDot => {
+            let base = repo.repo.merge_base(from_commit.id(), to_commit.id())?;
+            repo.repo.find_commit(base)?
+        }
+    };
+    let mut diff = repo.repo.diff_tree_to_tree(
+        Some(&base_commit.tree()?),
+        Some(&to_commit.tree()?),
+        Some(diff_opts),
+    )?;
+    find_similar(&mut diff, settings)?;
+    Ok(diff)
+}
+
+/// Changes introduced by a commit, compared to its first parent.
+pub fn commit_diff<'a>(
+    repo: &'a Repo,
+    rev: &str,
+    diff_opts: &mut DiffOptions,
+    settings: &DiffSettings,
+) -> Result<Diff<'a>, GitError> {
+    let commit = repo.repo.revparse_single(rev)?.peel_to_commit()?;
+    let parent_tree = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+    let mut diff = repo.repo.diff_tree_to_tree(
+        parent_tree.as_ref(),
+        Some(&commit.tree()?),
+        Some(diff_opts),
+    )?;
+    find_similar(&mut diff, settings)?;
+    Ok(diff)
+}
+
+/// Insertions and deletions of every file in `diff`.
+pub fn file_stats(diff: &Diff) -> Result<Vec<FileStats>, GitError> {
+    let mut stats = Vec::with_capacity(diff.deltas().len());
+    for idx in 0..diff.deltas().len() {
+        let delta = diff.get_delta(idx).ok_or(GitError::GetStatsFailed)?;
+        let path = delta
+            .new_file()
+            .path()
+            .or_else(|| delta.old_file().path())
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut file_stats = FileStats {
+            path,
+            insertions: 0,
+            deletions: 0,
+            binary: true,
+        };
+        if let Some(patch) = Patch::from_diff(diff, idx)? {
+            let (_, insertions, deletions) = patch.line_stats()?;
+            file_stats.insertions = insertions;
+            file_stats.deletions = deletions;
+            file_stats.binary = patch.delta().flags().is_binary();
+        }
+        stats.push(file_stats);
+    }
+    Ok(stats)
+}
+
+/// Turns a git2 `Diff` into one entry per file, each one holding its hunks
+/// and the lines of every hunk.
+pub fn diff_files(diff: &Diff) -> Result<Vec<DiffFile>, GitError> {
+    let mut files = Vec::with_capacity(diff.deltas().len());
+    for idx in 0..diff.deltas().len() {
+        files.push(diff_file(diff, idx, None)?);
+    }
+    Ok(files)
+}
+
+/// Builds the entry of the file at `idx` in `diff`. When `max_lines` is set
+/// the hunks stop after that many lines and the file is marked as truncated,
+/// its insertions and deletions still count the whole file.
+pub fn diff_file(diff: &Diff, idx: usize, max_lines: Option<usize>) -> Result<DiffFile, GitError> {
+    let delta = diff.get_delta(idx).ok_or(GitError::GetDiffFailed)?;
+    let patch = Patch::from_diff(diff, idx)?;
+    file_from_patch(&delta, patch.as_ref(), max_lines)
+}
+
+fn file_from_patch(
+    delta: &DiffDelta,
+    patch: Option<&Patch>,
+    max_lines: Option<usize>,
+) -> Result<DiffFile, GitError> {
+    let old_file = delta.old_file();
+    let new_file = delta.new_file();
+    let old_path = path_to_string(old_file.path());
+    let new_path = path_to_string(new_file.path());
+    let image = new_path
+        .as_ref()
+        .or(old_path.as_ref())
+        .and_then(|path| image_diff::image_mime(path))
+        .is_some();
+    let mut file = DiffFile {
+        old_path,
+        new_path,
+        status: delta.status().into(),
+        old_mode: old_file.mode().into(),
+        new_mode: new_file.mode().into(),
+        binary: delta.flags().is_binary(),
+        old_size: old_file.size(),
+        new_size: new_file.size(),
+        encoding: UTF_8.name().to_owned(),
+        image,
+        truncated: false,
+        insertions: 0,
+        deletions: 0,
+        hunks: vec![],
+    };
+
+    // Patches are only missing for binary files, their content can't be
+    // split into lines
+    let patch = match patch {
+        Some(patch) => patch,
+        None => {
+            file.binary = true;
+            return Ok(file);
+        }
+    };
+    // The patch delta has the flags and sizes found while loading the content
+    let patch_delta = patch.delta();
+    file.binary = patch_delta.flags().is_binary();
+    file.old_size = patch_delta.old_file().size();
+    file.new_size = patch_delta.new_file().size();
+    let (_, insertions, deletions) = patch.line_stats()?;
+    file.insertions = insertions;
+    file.deletions = deletions;
+    let encoding = detect_encoding(patch)?;
+    file.encoding = encoding.name().to_owned();
+
+    let mut lines_left = max_lines.unwrap_or(usize::MAX);
+    for hunk_idx in 0..patch.num_hunks() {
+        if lines_left == 0 {
+            file.truncated = true;
+            break;
+        }
+        let (hunk, lines_in_hunk) = patch.hunk(hunk_idx)?;
+        if lines_in_hunk > lines_left {
+            file.truncated = true;
+        }
+        let lines_in_hunk = lines_in_hunk.min(lines_left);
+        lines_left -= lines_in_hunk;
+        let mut lines = Vec::with_capacity(lines_in_hunk);
+        for line_idx in 0..lines_in_hunk {
+            let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+            lines.push(DiffLine {
+                content: decode(encoding, line.content()),
+                old_line: line.old_lineno(),
+                new_line: line.new_lineno(),
+                origin: line.origin(),
+                changes: vec![],
+                highlight: None,
+            });
+        }
+        word_diff::highlight_hunk(&mut lines);
+        file.hunks.push(DiffHunk {
+            header: decode(encoding, hunk.header()).trim_end().to_owned(),
+            old_start: hunk.old_start(),
+            old_lines: hunk.old_lines(),
+            new_start: hunk.new_start(),
+            new_lines: hunk.new_lines(),
+            lines,
+        });
+    }
+    Ok(file)
+}
+
+/// Guesses the encoding of the patch content from its first lines, files that
+/// are valid UTF-8 are always read as such.
+fn detect_encoding(patch: &Patch) -> Result<&'static Encoding, GitError> {
+    let mut sample: Vec<u8> = Vec::new();
+    'hunks: for hunk_idx in 0..patch.num_hunks() {
+        for line_idx in 0..patch.num_lines_in_hunk(hunk_idx)? {
+            sample.extend_from_slice(patch.line_in_hunk(hunk_idx, line_idx)?.content());
+            if sample.len() >= ENCODING_SAMPLE_SIZE {
+                break 'hunks;
+            }
+        }
+    }
+    if str::from_utf8(&sample).is_ok() {
+        return Ok(UTF_8);
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(&sample, true);
+    Ok(detector.guess(None, true))
+}
+
+/// Decodes `bytes`, replacing the sequences that aren't valid in `encoding`.
+fn decode(encoding: &'static Encoding, bytes: &[u8]) -> String {
+    encoding.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+fn path_to_string(path: Option<&Path>) -> Option<String> {
+    path.map(|path| path.to_string_lossy().into_owned())
+}
@@ -1,3 +1,4 @@
+use crate::state::CommitViolation;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -11,6 +12,11 @@ pub enum GitError {
     RemoteHeadNotFound,
     InvalidHead,
     InvalidCommit,
+    NotAnImage,
+    SigningFailed(String),
+    HookFailed { hook: String, output: String },
+    InvalidCommitMessage(Vec<CommitViolation>),
+    Cancelled,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SledError {
@@ -32,6 +38,24 @@ impl From<git2::Error> for GitError {
     }
 }
 
+impl From<regex::Error> for GitError {
+    fn from(err: regex::Error) -> Self {
+        GitError::Error(format!("{:#?}", err.to_string()))
+    }
+}
+
+impl From<std::io::Error> for GitError {
+    fn from(err: std::io::Error) -> Self {
+        GitError::Error(format!("{:#?}", err.to_string()))
+    }
+}
+
+impl From<SledError> for GitError {
+    fn from(err: SledError) -> Self {
+        GitError::Error(format!("{:#?}", err))
+    }
+}
+
 impl From<sled::Error> for SledError {
     fn from(err: sled::Error) -> Self {
         SledError::SledError(format!("{:#?}", err.to_string()))
@@ -43,3 +67,9 @@ impl From<String> for SledError {
         SledError::SledError(format!("{:#?}", err.to_string()))
     }
 }
+
+impl From<serde_json::Error> for SledError {
+    fn from(err: serde_json::Error) -> Self {
+        SledError::SledError(format!("{:#?}", err.to_string()))
+    }
+}
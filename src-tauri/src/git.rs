@@ -1,9 +1,21 @@
+use crate::diff;
 use crate::error::GitError;
+use crate::hooks::{Hook, Hooks};
+use crate::progress::ProgressReporter;
+use crate::signing::{self, SigningConfig};
+use crate::state::{DiscardSelection, DiscardSource, RefUpdate};
 use git2::{
-    build, AnnotatedCommit, AutotagOption, BranchType, Config, Error, FetchOptions, Reference,
-    Remote, RemoteCallbacks, Repository,
+    build, AnnotatedCommit, ApplyLocation, AutotagOption, BranchType, Commit, Diff, Error,
+    ErrorCode, FetchOptions, FetchPrune, Object, ObjectType, Oid, Patch, PushOptions, Reference,
+    Remote, RemoteCallbacks, Repository, Signature, Tree,
 };
-use std::io::{self, Write};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Id and content of a file in a tree, the index or the working tree, when
+/// it's there.
+pub type FileVersion = Option<(Oid, Vec<u8>)>;
 
 pub struct Repo {
     pub repo: Repository,
@@ -17,10 +29,30 @@ impl Repo {
         let repo = Repository::discover(path)?;
         Ok(Repo { repo })
     }
+    /// A handle of its own on the same repository, to work with on another
+    /// thread.
+    pub fn reopen(&self) -> Result<Self, GitError> {
+        let repo = Repository::open(self.repo.path())?;
+        Ok(Repo { repo })
+    }
+    /// Path of the `.git` directory, used to key per-repository settings.
+    pub fn path(&self) -> String {
+        self.repo.path().to_string_lossy().into_owned()
+    }
     pub fn get_current_branch_name(&self) -> Result<String, GitError> {
-        let head = self.repo.head()?;
-        let head_name = head.shorthand().unwrap();
-        Ok(head_name.to_string())
+        match self.repo.head() {
+            Ok(head) => {
+                let head_name = head.shorthand().unwrap();
+                Ok(head_name.to_string())
+            }
+            // Before the first commit HEAD points to a branch that doesn't exist yet
+            Err(e) if e.code() == ErrorCode::UnbornBranch => {
+                let head = self.repo.find_reference("HEAD")?;
+                let target = head.symbolic_target().ok_or(GitError::InvalidHead)?;
+                Ok(target.trim_start_matches("refs/heads/").to_string())
+            }
+            Err(e) => Err(e.into()),
+        }
     }
     pub fn get_repo_name(&self) -> Result<(String, String), GitError> {
         let path_name = self.repo.path().to_str().unwrap().to_string();
@@ -42,52 +74,427 @@ impl Repo {
 
         Err(GitError::GitCheckoutError)
     }
+
+    /// Returns the tree HEAD points to, or `None` while the branch is unborn.
+    pub fn head_tree(&self) -> Result<Option<Tree<'_>>, GitError> {
+        match self.repo.head() {
+            Ok(head) => Ok(Some(head.peel_to_tree()?)),
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the commit HEAD points to, or `None` while the branch is unborn.
+    pub fn head_commit(&self) -> Result<Option<Commit<'_>>, GitError> {
+        match self.repo.head() {
+            Ok(head) => Ok(Some(head.peel_to_commit()?)),
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes a commit object, signed with the configured program when
+    /// `commit.gpgsign` is set. HEAD isn't moved.
+    pub fn create_commit(
+        &self,
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+        tree: &Tree,
+        parents: &[&Commit],
+    ) -> Result<Oid, GitError> {
+        let config = self.repo.config()?;
+        if !signing::should_sign_commits(&config) {
+            let oid = self
+                .repo
+                .commit(None, author, committer, message, tree, parents)?;
+            return Ok(oid);
+        }
+        let buffer = self
+            .repo
+            .commit_create_buffer(author, committer, message, tree, parents)?;
+        let buffer = buffer.as_str().ok_or(GitError::InvalidCommit)?;
+        let signature = SigningConfig::from_config(&config)?.sign(buffer, committer)?;
+        let oid = self.repo.commit_signed(buffer, &signature, None)?;
+        Ok(oid)
+    }
+
+    /// Annotated tag on `target`, signed with the configured program when
+    /// `sign` is set.
+    pub fn create_annotated_tag(
+        &self,
+        name: &str,
+        target: &Object,
+        message: &str,
+        sign: bool,
+        force: bool,
+    ) -> Result<Oid, GitError> {
+        let tagger = self.repo.signature()?;
+        if !sign {
+            let oid = self.repo.tag(name, target, &tagger, message, force)?;
+            return Ok(oid);
+        }
+        let refname = format!("refs/tags/{}", name);
+        if !Reference::is_valid_name(&refname) {
+            return Err(GitError::Error(format!("{} is not a valid tag name", name)));
+        }
+        // libgit2 can't sign tags, the object is written the way git does it
+        let mut buffer = format!(
+            "object {}\ntype {}\ntag {}\ntagger {}\n\n{}",
+            target.id(),
+            target.kind().unwrap_or(ObjectType::Commit).str(),
+            name,
+            format_signature(&tagger),
+            message
+        );
+        if !buffer.ends_with('\n') {
+            buffer.push('\n');
+        }
+        let config = self.repo.config()?;
+        let signature = SigningConfig::from_config(&config)?.sign(&buffer, &tagger)?;
+        buffer.push_str(&signature);
+        let oid = self.repo.odb()?.write(ObjectType::Tag, buffer.as_bytes())?;
+        self.repo
+            .reference(&refname, oid, force, &format!("tag: {}", name))?;
+        Ok(oid)
+    }
+
+    /// Points the current branch at `oid`, creating the branch when HEAD is
+    /// unborn, or moves a detached HEAD.
+    pub fn update_head(&self, oid: Oid, reflog_msg: &str) -> Result<(), GitError> {
+        let head = self.repo.find_reference("HEAD")?;
+        match head.symbolic_target() {
+            Some(branch_ref) => {
+                self.repo.reference(branch_ref, oid, true, reflog_msg)?;
+            }
+            None => self.repo.set_head_detached(oid)?,
+        }
+        Ok(())
+    }
+
+    /// Id and content of `path` in the HEAD commit.
+    pub fn head_content(&self, path: &str) -> Result<FileVersion, GitError> {
+        let tree = match self.head_tree()? {
+            Some(tree) => tree,
+            None => return Ok(None),
+        };
+        match tree.get_path(Path::new(path)) {
+            Ok(entry) => {
+                let blob = self.repo.find_blob(entry.id())?;
+                Ok(Some((blob.id(), blob.content().to_vec())))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Id and content of `path` in the index.
+    pub fn index_content(&self, path: &str) -> Result<FileVersion, GitError> {
+        let index = self.repo.index()?;
+        match index.get_path(Path::new(path), 0) {
+            Some(entry) => {
+                let blob = self.repo.find_blob(entry.id)?;
+                Ok(Some((blob.id(), blob.content().to_vec())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Id the content of `path` in the working tree would get as a blob, and
+    /// the content itself.
+    pub fn workdir_content(&self, path: &str) -> Result<FileVersion, GitError> {
+        let workdir = self.repo.workdir().ok_or(GitError::RepoNotFound)?;
+        let file_path = workdir.join(path);
+        if !file_path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read(file_path)?;
+        let oid = Oid::hash_object(ObjectType::Blob, &content)?;
+        Ok(Some((oid, content)))
+    }
+
+    /// Old and new content of a file as compared by the staged diff (HEAD to
+    /// index) or the unstaged one (index to working tree).
+    pub fn diff_sides(
+        &self,
+        old_path: &str,
+        new_path: &str,
+        staged: bool,
+    ) -> Result<(FileVersion, FileVersion), GitError> {
+        if staged {
+            Ok((self.head_content(old_path)?, self.index_content(new_path)?))
+        } else {
+            Ok((
+                self.index_content(old_path)?,
+                self.workdir_content(new_path)?,
+            ))
+        }
+    }
+
+    /// Reverts a file in the working tree to its version in `source` without
+    /// touching the index. Files that `source` doesn't know about are removed,
+    /// to the OS trash if `to_trash` is set, unless they're staged: reverting
+    /// those to HEAD would delete what's in the index.
+    pub fn discard_file(
+        &self,
+        path: &str,
+        source: &DiscardSource,
+        to_trash: bool,
+    ) -> Result<(), GitError> {
+        let mut index = self.repo.index()?;
+        let in_index = index.get_path(Path::new(path), 0).is_some();
+        let mut checkout = build::CheckoutBuilder::new();
+        checkout
+            .force()
+            .update_index(false)
+            .path(escape_pathspec(path));
+
+        match source {
+            DiscardSource::Index => {
+                if in_index {
+                    self.repo
+                        .checkout_index(Some(&mut index), Some(&mut checkout))?;
+                    return Ok(());
+                }
+            }
+            DiscardSource::Head => {
+                if let Some(tree) = self.head_tree()? {
+                    if tree.get_path(Path::new(path)).is_ok() {
+                        self.repo
+                            .checkout_tree(tree.as_object(), Some(&mut checkout))?;
+                        return Ok(());
+                    }
+                }
+                if in_index {
+                    return Err(GitError::Error(format!(
+                        "{} is staged but not in HEAD, discard it from the index instead",
+                        path
+                    )));
+                }
+            }
+        }
+
+        let workdir = self.repo.workdir().ok_or(GitError::RepoNotFound)?;
+        let file_path = workdir.join(path);
+        if !file_path.exists() {
+            return Ok(());
+        }
+        if to_trash {
+            trash::delete(&file_path).map_err(|e| GitError::Error(e.to_string()))?;
+        } else if file_path.is_dir() {
+            fs::remove_dir_all(&file_path)?;
+        } else {
+            fs::remove_file(&file_path)?;
+        }
+        Ok(())
+    }
+
+    /// Reverts only the selected hunks or lines of a file in the working tree,
+    /// leaving the rest of its edits untouched.
+    pub fn discard_selection(
+        &self,
+        path: &str,
+        selection: &DiscardSelection,
+    ) -> Result<(), GitError> {
+        // Same settings as the diff shown to the user so hunk indexes match
+        let settings = diff::load_settings(self)?;
+        let mut diff_opts = diff::diff_options(Some(path), &settings);
+        let diff = diff::unstaged_diff(self, &mut diff_opts, &settings)?;
+        if let Some(buffer) = build_reverse_patch(path, &diff, selection)? {
+            let reverse_diff = Diff::from_buffer(&buffer)?;
+            self.repo
+                .apply(&reverse_diff, ApplyLocation::WorkDir, None)?;
+        }
+        Ok(())
+    }
+}
+
+/// A line of a reverse hunk, with the "No newline at end of file" marker that
+/// follows it when there's one.
+struct ReverseLine<'a> {
+    origin: u8,
+    content: &'a [u8],
+    no_newline: Option<&'a [u8]>,
+}
+
+/// Builds a patch that goes from the working tree back towards the index,
+/// only for the selected part of the index-to-workdir `diff` of `path`.
+/// Unselected additions become context and unselected deletions are dropped.
+fn build_reverse_patch(
+    path: &str,
+    diff: &Diff,
+    selection: &DiscardSelection,
+) -> Result<Option<Vec<u8>>, GitError> {
+    let patch = match Patch::from_diff(diff, 0)? {
+        Some(patch) => patch,
+        None => return Ok(None),
+    };
+    let delta = patch.delta();
+    // Binary files have no hunks to select from
+    if delta.flags().is_binary() {
+        return Err(GitError::Error(format!(
+            "{} is binary, only the whole file can be discarded",
+            path
+        )));
+    }
+    let mut hunks: Vec<u8> = Vec::new();
+    // Lines added minus lines removed by the hunks already written, libgit2
+    // applies each hunk at its new start so it must account for them.
+    let mut offset: i64 = 0;
+
+    for hunk_idx in 0..patch.num_hunks() {
+        let (hunk, lines_in_hunk) = patch.hunk(hunk_idx)?;
+        let mut lines: Vec<ReverseLine> = Vec::new();
+        let mut last_written = false;
+
+        for line_idx in 0..lines_in_hunk {
+            let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+            let selected = match selection {
+                DiscardSelection::Hunks(hunks) => hunks.contains(&hunk_idx),
+                DiscardSelection::Lines(ids) => ids
+                    .iter()
+                    .any(|id| id.old_line == line.old_lineno() && id.new_line == line.new_lineno()),
+            };
+            let origin = match (line.origin(), selected) {
+                (' ', _) | ('+', false) => b' ',
+                ('+', true) => b'-',
+                ('-', true) => b'+',
+                // "No newline at end of file" markers follow the line they refer to
+                ('=', _) | ('>', _) | ('<', _) => {
+                    if let (true, Some(last)) = (last_written, lines.last_mut()) {
+                        last.no_newline = Some(line.content());
+                    }
+                    continue;
+                }
+                _ => {
+                    last_written = false;
+                    continue;
+                }
+            };
+            lines.push(ReverseLine {
+                origin,
+                content: line.content(),
+                no_newline: None,
+            });
+            last_written = true;
+        }
+
+        if lines.iter().all(|line| line.origin == b' ') {
+            continue;
+        }
+        // Reversing swaps the removed and added lines of every change, put
+        // the removed ones first again as patches expect
+        for block in lines.split_mut(|line| line.origin == b' ') {
+            block.sort_by_key(|line| line.origin != b'-');
+        }
+        // A restored line that ended the index version may now be followed
+        // by lines kept from the working tree
+        let last_new = lines.iter().rposition(|line| line.origin != b'-');
+        let mut body: Vec<u8> = Vec::new();
+        let mut old_lines: u32 = 0;
+        let mut new_lines: u32 = 0;
+        for (idx, line) in lines.iter().enumerate() {
+            match line.origin {
+                b' ' => {
+                    old_lines += 1;
+                    new_lines += 1;
+                }
+                b'-' => old_lines += 1,
+                _ => new_lines += 1,
+            }
+            body.push(line.origin);
+            body.extend_from_slice(line.content);
+            match line.no_newline {
+                Some(_) if line.origin == b'+' && Some(idx) != last_new => body.push(b'\n'),
+                Some(marker) => body.extend_from_slice(marker),
+                None => {}
+            }
+        }
+
+        // The working tree side of the original hunk is the old side of the reverse one
+        let old_start = hunk.new_start() as i64;
+        let position = if hunk.new_lines() == 0 {
+            old_start
+        } else {
+            old_start - 1
+        };
+        let new_start = position + offset + 1;
+        hunks.extend_from_slice(
+            format!(
+                "@@ -{},{} +{},{} @@\n",
+                old_start, old_lines, new_start, new_lines
+            )
+            .as_bytes(),
+        );
+        hunks.extend_from_slice(&body);
+        offset += new_lines as i64 - old_lines as i64;
+    }
+
+    if hunks.is_empty() {
+        return Ok(None);
+    }
+    // A file deleted from the working tree is created again
+    let header = if delta.new_file().exists() {
+        format!("diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n", path)
+    } else {
+        format!(
+            "diff --git a/{0} b/{0}\nnew file mode {1:o}\n--- /dev/null\n+++ b/{0}\n",
+            path,
+            u32::from(delta.old_file().mode())
+        )
+    };
+    let mut buffer = header.into_bytes();
+    buffer.extend_from_slice(&hunks);
+    Ok(Some(buffer))
 }
 
 pub fn do_fetch<'a>(
     repo: &'a Repository,
     refs: &[&str],
     remote: &'a mut Remote,
-) -> Result<AnnotatedCommit<'a>, Error> {
-    let mut cb = RemoteCallbacks::new();
-    let git_config = Config::open_default().unwrap();
-    let mut ch = git2_credentials::CredentialHandler::new(git_config);
-    cb.credentials(move |url, username, allowed| ch.try_next_credential(url, username, allowed));
-    // Print out our transfer progress.
-    cb.transfer_progress(|stats| {
-        if stats.received_objects() == stats.total_objects() {
-            print!(
-                "Resolving deltas {}/{}\r",
-                stats.indexed_deltas(),
-                stats.total_deltas()
-            );
-        } else if stats.total_objects() > 0 {
-            print!(
-                "Received {}/{} objects ({}) in {} bytes\r",
-                stats.received_objects(),
-                stats.total_objects(),
-                stats.indexed_objects(),
-                stats.received_bytes()
-            );
-        }
-        io::stdout().flush().unwrap();
-        true
-    });
+    progress: &ProgressReporter,
+) -> Result<AnnotatedCommit<'a>, GitError> {
+    let mut cb = get_remote_callbacks();
+    progress.attach(&mut cb);
 
     let mut fo = FetchOptions::new();
     fo.remote_callbacks(cb);
     // Always fetch all tags.
     // Perform a download and also update tips
     fo.download_tags(AutotagOption::All);
-    remote.fetch(refs, Some(&mut fo), None)?;
-
-    // If there are local objects (we got a thin pack), then tell the user
-    // how many objects we saved from having to cross the network.
+    remote
+        .fetch(refs, Some(&mut fo), None)
+        .map_err(|e| progress.error(e))?;
 
     let fetch_head = repo.find_reference("FETCH_HEAD")?;
     Ok(repo.reference_to_annotated_commit(&fetch_head)?)
 }
 
+/// Fetches `remote` and reports every reference it created, moved or pruned.
+/// Without an explicit `prune`, `remote.<name>.prune` and `fetch.prune` decide.
+pub fn fetch_with_summary(
+    remote: &mut Remote,
+    prune: Option<bool>,
+    progress: &ProgressReporter,
+) -> Result<Vec<RefUpdate>, GitError> {
+    let mut cb = get_remote_callbacks();
+    progress.attach(&mut cb);
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(cb);
+    fo.prune(match prune {
+        Some(true) => FetchPrune::On,
+        Some(false) => FetchPrune::Off,
+        None => FetchPrune::Unspecified,
+    });
+    remote
+        .fetch(&[] as &[&str], Some(&mut fo), None)
+        .map_err(|e| progress.error(e))?;
+    Ok(progress.take_updates())
+}
+
 pub fn fast_forward(
     repo: &Repository,
     reference: &mut Reference,
@@ -113,7 +520,7 @@ pub fn normal_merge(
     repo: &Repository,
     local: &AnnotatedCommit,
     remote: &AnnotatedCommit,
-) -> Result<(), Error> {
+) -> Result<bool, Error> {
     let local_tree = repo.find_commit(local.id())?.tree()?;
     let remote_tree = repo.find_commit(remote.id())?.tree()?;
     let ancestor = repo
@@ -123,7 +530,7 @@ pub fn normal_merge(
 
     if idx.has_conflicts() {
         repo.checkout_index(Some(&mut idx), None)?;
-        return Ok(());
+        return Ok(false);
     }
     let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
     // now create the merge commit
@@ -142,14 +549,14 @@ pub fn normal_merge(
     )?;
     // Set working tree to match head.
     repo.checkout_head(None)?;
-    Ok(())
+    Ok(true)
 }
 
 pub fn do_merge<'a>(
     repo: &'a Repository,
     remote_branch: &str,
     fetch_commit: AnnotatedCommit<'a>,
-) -> Result<(), Error> {
+) -> Result<bool, Error> {
     // 1. do a merge analysis
     let (merge_analysis, _) = repo.merge_analysis(&[&fetch_commit])?;
     // 2. Do the appropriate merge
@@ -179,16 +586,104 @@ pub fn do_merge<'a>(
                 ))?;
             }
         };
-        return Ok(());
+        return Ok(true);
     }
     if merge_analysis.is_normal() {
         // do a normal merge
         let head_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
-        normal_merge(&repo, &head_commit, &fetch_commit)?;
-        return Ok(());
+        return normal_merge(&repo, &head_commit, &fetch_commit);
     }
 
-    return Ok(());
+    return Ok(false);
+}
+
+/// Pushes `refspecs` to `remote` once the pre-push hook accepts them. A
+/// refspec without source deletes the remote reference. Cancelling only works
+/// until the pack starts uploading, git2 can't stop the upload itself.
+pub fn push_refspecs(
+    repo: &Repository,
+    hooks: &Hooks,
+    progress: &ProgressReporter,
+    remote: &mut Remote,
+    refspecs: &[String],
+) -> Result<(), GitError> {
+    // Remotes given as a URL have no name
+    let remote_name = remote
+        .name()
+        .or_else(|| remote.url())
+        .unwrap_or_default()
+        .to_string();
+    // The hook reads a line per updated reference, the same as git sends it,
+    // with the ids the remote advertises and zero for references it lacks.
+    // git2 crashes listing a remote without references, one advertising HEAD
+    // (only fetch connections do) is sure to have some.
+    let mut remote_oids = HashMap::new();
+    {
+        let conn =
+            remote.connect_auth(git2::Direction::Fetch, Some(get_remote_callbacks()), None)?;
+        if conn.default_branch().is_ok() {
+            for head in conn.list()? {
+                remote_oids.insert(head.name().to_string(), head.oid());
+            }
+        }
+    }
+    let mut updates = String::new();
+    for refspec in refspecs {
+        let refspec = refspec.trim_start_matches('+');
+        let (src, dst) = refspec.split_once(':').unwrap_or((refspec, refspec));
+        let remote_oid = remote_oids.get(dst).copied().unwrap_or_else(Oid::zero);
+        if src.is_empty() {
+            updates.push_str(&format!(
+                "(delete) {} {} {}\n",
+                Oid::zero(),
+                dst,
+                remote_oid
+            ));
+        } else {
+            let local_oid = repo.refname_to_id(src)?;
+            updates.push_str(&format!("{} {} {} {}\n", src, local_oid, dst, remote_oid));
+        }
+    }
+    let url = remote.url().unwrap_or_default().to_string();
+    hooks.run(Hook::PrePush, &[&remote_name, &url], updates.as_bytes())?;
+    progress.check_cancelled()?;
+
+    let mut rejected = vec![];
+    {
+        let mut callbacks = get_remote_callbacks();
+        progress.attach(&mut callbacks);
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(status) = status {
+                rejected.push(format!("{}: {}", refname, status));
+            }
+            Ok(())
+        });
+        let mut push_options = PushOptions::new();
+        push_options.remote_callbacks(callbacks);
+        remote
+            .push(refspecs, Some(&mut push_options))
+            .map_err(|e| progress.error(e))?;
+    }
+    remote.disconnect()?;
+    if !rejected.is_empty() {
+        return Err(GitError::Error(rejected.join("\n")));
+    }
+    Ok(())
+}
+
+/// Signature the way git writes it in objects, with the time zone offset.
+fn format_signature(signature: &Signature) -> String {
+    let when = signature.when();
+    let offset = when.offset_minutes();
+    format!(
+        "{} <{}> {} {}{:02}{:02}",
+        signature.name().unwrap_or_default(),
+        signature.email().unwrap_or_default(),
+        when.seconds(),
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
 }
 
 pub fn get_remote_callbacks() -> RemoteCallbacks<'static> {
@@ -214,3 +709,257 @@ pub const INTERESTING_STAGED: git2::Status = git2::Status::from_bits_truncate(
 );
 
 pub const DEFAULT_REMOTE: &str = "origin";
+
+/// Checkouts take pathspecs, escaping the wildcards makes one match only the
+/// file named `path`.
+fn escape_pathspec(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for (i, c) in path.chars().enumerate() {
+        if matches!(c, '*' | '?' | '[' | '\\') || (i == 0 && c == '!') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::DiffLineId;
+    use git2::DiffOptions;
+    use tempfile::TempDir;
+
+    /// Stages `index` as the content of a file, writes `workdir` over it, and
+    /// discards `selection` of the unstaged diff built with `context_lines`.
+    /// A repository with `index` staged as "file".
+    fn staged_repo(index: &[u8]) -> (TempDir, Repository) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        fs::write(dir.path().join("file"), index).unwrap();
+        let mut repo_index = repo.index().unwrap();
+        repo_index.add_path(Path::new("file")).unwrap();
+        repo_index.write().unwrap();
+        (dir, repo)
+    }
+
+    fn apply_reverse(
+        repo: &Repository,
+        context_lines: u32,
+        selection: &DiscardSelection,
+    ) -> Result<(), GitError> {
+        let mut diff_opts = DiffOptions::new();
+        diff_opts.pathspec("file").context_lines(context_lines);
+        let diff = repo.diff_index_to_workdir(None, Some(&mut diff_opts))?;
+        if let Some(buffer) = build_reverse_patch("file", &diff, selection)? {
+            let reverse_diff = Diff::from_buffer(&buffer)?;
+            repo.apply(&reverse_diff, ApplyLocation::WorkDir, None)?;
+        }
+        Ok(())
+    }
+
+    fn discard(
+        index: &str,
+        workdir: &str,
+        context_lines: u32,
+        selection: DiscardSelection,
+    ) -> String {
+        let (dir, repo) = staged_repo(index.as_bytes());
+        let file_path = dir.path().join("file");
+        fs::write(&file_path, workdir).unwrap();
+        apply_reverse(&repo, context_lines, &selection).unwrap();
+        fs::read_to_string(&file_path).unwrap()
+    }
+
+    fn line(old_line: Option<u32>, new_line: Option<u32>) -> DiffLineId {
+        DiffLineId { old_line, new_line }
+    }
+
+    #[test]
+    fn discards_hunk_without_final_newline() {
+        let content = discard("a\nb", "a\nc", 3, DiscardSelection::Hunks(vec![0]));
+        assert_eq!(content, "a\nb");
+    }
+
+    #[test]
+    fn restores_deleted_line_without_final_newline() {
+        let selection = DiscardSelection::Lines(vec![line(Some(2), None)]);
+        assert_eq!(discard("a\nb", "a\nc", 3, selection), "a\nb\nc");
+    }
+
+    #[test]
+    fn removes_added_line_without_final_newline() {
+        let selection = DiscardSelection::Lines(vec![line(None, Some(2))]);
+        assert_eq!(discard("a\nb", "a\nc", 3, selection), "a\n");
+    }
+
+    #[test]
+    fn discards_one_hunk_of_many() {
+        let lines = |edit: &dyn Fn(u32) -> String| -> String { (1..=20).map(edit).collect() };
+        let index = lines(&|n| format!("{}\n", n));
+        let first_edit = |n| match n {
+            2 => "two\nextra\n".to_string(),
+            n => format!("{}\n", n),
+        };
+        let workdir = lines(&|n| match n {
+            18 => "eighteen\n".to_string(),
+            n => first_edit(n),
+        });
+        assert_eq!(
+            discard(&index, &workdir, 3, DiscardSelection::Hunks(vec![1])),
+            lines(&first_edit)
+        );
+        assert_eq!(
+            discard(&index, &workdir, 3, DiscardSelection::Hunks(vec![0, 1])),
+            index
+        );
+    }
+
+    #[test]
+    fn discards_without_context_lines() {
+        let index = "1\n2\n3\n4\n5\n";
+        let workdir = "1\nX\n3\n4\nY\nZ\n";
+        assert_eq!(
+            discard(index, workdir, 0, DiscardSelection::Hunks(vec![0, 1])),
+            index
+        );
+        assert_eq!(
+            discard(index, workdir, 0, DiscardSelection::Hunks(vec![1])),
+            "1\nX\n3\n4\n5\n"
+        );
+    }
+
+    #[test]
+    fn discards_add_only_and_delete_only_hunks() {
+        let index = "a\nb\n";
+        assert_eq!(
+            discard(index, "a\nx\nb\n", 3, DiscardSelection::Hunks(vec![0])),
+            index
+        );
+        assert_eq!(
+            discard(index, "a\n", 3, DiscardSelection::Hunks(vec![0])),
+            index
+        );
+        assert_eq!(
+            discard(index, "a\nx\nb\n", 0, DiscardSelection::Hunks(vec![0])),
+            index
+        );
+    }
+
+    #[test]
+    fn restores_hunk_of_deleted_file() {
+        let (dir, repo) = staged_repo(b"a\nb\n");
+        let file_path = dir.path().join("file");
+        fs::remove_file(&file_path).unwrap();
+        apply_reverse(&repo, 3, &DiscardSelection::Hunks(vec![0])).unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn refuses_to_discard_part_of_binary_file() {
+        let (dir, repo) = staged_repo(b"a\0b\n");
+        let file_path = dir.path().join("file");
+        fs::write(&file_path, b"a\0c\n").unwrap();
+        assert!(apply_reverse(&repo, 3, &DiscardSelection::Hunks(vec![0])).is_err());
+        assert_eq!(fs::read(&file_path).unwrap(), b"a\0c\n");
+    }
+
+    /// A repository with `files` committed and then written over with
+    /// "changed".
+    fn changed_repo(files: &[&str]) -> (TempDir, Repo) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut index = repo.index().unwrap();
+        for file in files {
+            fs::write(dir.path().join(file), format!("{}\n", file)).unwrap();
+            index.add_path(Path::new(file)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("a", "a@b").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "first", &tree, &[])
+            .unwrap();
+        drop(tree);
+        for file in files {
+            fs::write(dir.path().join(file), "changed\n").unwrap();
+        }
+        (dir, Repo::new(repo))
+    }
+
+    #[test]
+    fn discards_only_the_file_named_like_a_pattern() {
+        let (dir, repo) = changed_repo(&["a*", "ab", "[a]", "a", "!b", "b"]);
+        repo.discard_file("a*", &DiscardSource::Head, false)
+            .unwrap();
+        repo.discard_file("[a]", &DiscardSource::Index, false)
+            .unwrap();
+        repo.discard_file("!b", &DiscardSource::Index, false)
+            .unwrap();
+        for file in ["a*", "[a]", "!b"] {
+            assert_eq!(
+                fs::read_to_string(dir.path().join(file)).unwrap(),
+                format!("{}\n", file)
+            );
+        }
+        for file in ["ab", "a", "b"] {
+            assert_eq!(
+                fs::read_to_string(dir.path().join(file)).unwrap(),
+                "changed\n"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_new_staged_file_when_discarding_to_head() {
+        let (dir, repo) = changed_repo(&["a"]);
+        fs::write(dir.path().join("new"), "staged\n").unwrap();
+        let mut index = repo.repo.index().unwrap();
+        index.add_path(Path::new("new")).unwrap();
+        index.write().unwrap();
+        fs::write(dir.path().join("new"), "edited\n").unwrap();
+
+        assert!(repo
+            .discard_file("new", &DiscardSource::Head, false)
+            .is_err());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("new")).unwrap(),
+            "edited\n"
+        );
+        repo.discard_file("new", &DiscardSource::Index, false)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("new")).unwrap(),
+            "staged\n"
+        );
+    }
+
+    #[test]
+    fn removes_untracked_file() {
+        let (dir, repo) = changed_repo(&["a"]);
+        fs::write(dir.path().join("untracked"), "x").unwrap();
+        repo.discard_file("untracked", &DiscardSource::Head, false)
+            .unwrap();
+        assert!(!dir.path().join("untracked").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restores_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let (dir, repo) = changed_repo(&["script"]);
+        let path = dir.path().join("script");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut index = repo.repo.index().unwrap();
+        index.add_path(Path::new("script")).unwrap();
+        index.write().unwrap();
+        fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink("elsewhere", &path).unwrap();
+
+        repo.discard_file("script", &DiscardSource::Index, false)
+            .unwrap();
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.is_file());
+        assert_ne!(metadata.permissions().mode() & 0o111, 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "changed\n");
+    }
+}
@@ -0,0 +1,158 @@
+use crate::error::GitError;
+use crate::git::Repo;
+use crate::state::{DiffFile, HighlightSpan};
+use encoding_rs::{Encoding, UTF_8};
+use git2::{AttrCheckFlags, Oid};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+/// The cache is emptied once it holds this many highlighted blobs.
+const MAX_CACHED_BLOBS: usize = 256;
+
+type HighlightedLines = Arc<Vec<Vec<HighlightSpan>>>;
+
+/// Highlighted lines of every blob already tokenized, by blob id and syntax.
+#[derive(Default)]
+pub struct HighlightCache {
+    blobs: Mutex<HashMap<(Oid, String), HighlightedLines>>,
+}
+
+/// Fills the highlight spans of every line of `file`, tokenizing the whole
+/// old and new contents so multi-line constructs are highlighted correctly.
+/// Files in a language that can't be detected are left untouched.
+pub fn highlight_file(
+    repo: &Repo,
+    cache: &HighlightCache,
+    file: &mut DiffFile,
+    staged: bool,
+) -> Result<(), GitError> {
+    if file.binary {
+        return Ok(());
+    }
+    let (old_path, new_path) = match (&file.old_path, &file.new_path) {
+        (Some(old_path), Some(new_path)) => (old_path.clone(), new_path.clone()),
+        _ => return Ok(()),
+    };
+    let syntax = match find_syntax(repo, &new_path) {
+        Some(syntax) => syntax,
+        None => return Ok(()),
+    };
+    let encoding = Encoding::for_label(file.encoding.as_bytes()).unwrap_or(UTF_8);
+    let (old, new) = repo.diff_sides(&old_path, &new_path, staged)?;
+    let old_lines = old.map(|(oid, content)| cache.highlight(oid, &content, syntax, encoding));
+    let new_lines = new.map(|(oid, content)| cache.highlight(oid, &content, syntax, encoding));
+
+    for hunk in file.hunks.iter_mut() {
+        for line in hunk.lines.iter_mut() {
+            let (lines, line_number) = match line.origin {
+                '-' => (&old_lines, line.old_line),
+                '+' | ' ' => (&new_lines, line.new_line),
+                _ => continue,
+            };
+            if let (Some(lines), Some(line_number)) = (lines, line_number) {
+                line.highlight = lines.get(line_number as usize - 1).cloned();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Language from the `linguist-language` gitattribute, or else from the file
+/// extension.
+fn find_syntax(repo: &Repo, path: &str) -> Option<&'static SyntaxReference> {
+    if let Ok(Some(language)) = repo.repo.get_attr(
+        Path::new(path),
+        "linguist-language",
+        AttrCheckFlags::default(),
+    ) {
+        let syntax = SYNTAX_SET
+            .find_syntax_by_name(language)
+            .or_else(|| SYNTAX_SET.find_syntax_by_token(language));
+        if syntax.is_some() {
+            return syntax;
+        }
+    }
+    let extension = Path::new(path).extension()?.to_str()?;
+    SYNTAX_SET.find_syntax_by_extension(extension)
+}
+
+impl HighlightCache {
+    fn highlight(
+        &self,
+        oid: Oid,
+        content: &[u8],
+        syntax: &SyntaxReference,
+        encoding: &'static Encoding,
+    ) -> HighlightedLines {
+        let key = (oid, syntax.name.clone());
+        if let Some(lines) = self.blobs.lock().unwrap().get(&key) {
+            return lines.clone();
+        }
+        let text = encoding.decode_without_bom_handling(content).0;
+        let lines = Arc::new(highlight_text(&text, syntax));
+        let mut blobs = self.blobs.lock().unwrap();
+        if blobs.len() >= MAX_CACHED_BLOBS {
+            blobs.clear();
+        }
+        blobs.insert(key, lines.clone());
+        lines
+    }
+}
+
+/// Spans of every line of `text`, offsets counted in characters. Text with
+/// no scope besides the language one gets no span.
+fn highlight_text(text: &str, syntax: &SyntaxReference) -> Vec<Vec<HighlightSpan>> {
+    let mut parse_state = ParseState::new(syntax);
+    let mut scopes = ScopeStack::new();
+    let mut highlighted = vec![];
+
+    for line in LinesWithEndings::from(text) {
+        let ops = match parse_state.parse_line(line, &SYNTAX_SET) {
+            Ok(ops) => ops,
+            // Grammar errors only lose the colors of the rest of the file
+            Err(_) => break,
+        };
+        let mut spans = vec![];
+        let mut span_start = 0;
+        for (byte_idx, op) in ops {
+            push_span(&mut spans, line, span_start, byte_idx, &scopes);
+            span_start = byte_idx;
+            if scopes.apply(&op).is_err() {
+                break;
+            }
+        }
+        push_span(&mut spans, line, span_start, line.len(), &scopes);
+        highlighted.push(spans);
+    }
+    highlighted
+}
+
+fn push_span(
+    spans: &mut Vec<HighlightSpan>,
+    line: &str,
+    start: usize,
+    end: usize,
+    scopes: &ScopeStack,
+) {
+    // The first scope is the language itself, it's on every character
+    let scope = match scopes.as_slice() {
+        [_, .., innermost] => innermost.build_string(),
+        _ => return,
+    };
+    let text = line[start..end].trim_end_matches(&['\r', '\n'][..]);
+    if text.is_empty() {
+        return;
+    }
+    let start = line[..start].chars().count();
+    spans.push(HighlightSpan {
+        start,
+        end: start + text.chars().count(),
+        scope,
+    });
+}
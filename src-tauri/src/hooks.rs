@@ -0,0 +1,177 @@
+use crate::error::GitError;
+use crate::state::HookOutput;
+use git2::Repository;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use tauri::Window;
+
+/// Event carrying every line a hook prints, on stdout or stderr.
+pub const HOOK_OUTPUT_EVENT: &str = "hook-output";
+
+#[derive(Clone, Copy)]
+pub enum Hook {
+    PreCommit,
+    PrepareCommitMsg,
+    CommitMsg,
+    PostCommit,
+    PrePush,
+    PostMerge,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreCommit => "pre-commit",
+            Hook::PrepareCommitMsg => "prepare-commit-msg",
+            Hook::CommitMsg => "commit-msg",
+            Hook::PostCommit => "post-commit",
+            Hook::PrePush => "pre-push",
+            Hook::PostMerge => "post-merge",
+        }
+    }
+}
+
+/// Runs the hooks of a repository the way git does, sending their output to
+/// the window as it comes.
+pub struct Hooks<'a> {
+    repo: &'a Repository,
+    window: &'a Window,
+    skip: bool,
+}
+
+impl<'a> Hooks<'a> {
+    /// With `skip` set no hook runs at all.
+    pub fn new(repo: &'a Repository, window: &'a Window, skip: bool) -> Self {
+        Hooks { repo, window, skip }
+    }
+
+    /// Runs `hook` when it exists and is executable. A failing hook returns
+    /// `GitError::HookFailed` with everything it printed.
+    pub fn run(&self, hook: Hook, args: &[&str], stdin: &[u8]) -> Result<(), GitError> {
+        if self.skip {
+            return Ok(());
+        }
+        let path = match self.hook_path(hook) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut child = hook_command(&path)
+            .args(args)
+            .current_dir(self.work_dir())
+            .env("GIT_INDEX_FILE", self.repo.path().join("index"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (sender, receiver) = mpsc::channel();
+        let readers = vec![
+            child
+                .stdout
+                .take()
+                .map(|out| read_lines(out, sender.clone())),
+            child.stderr.take().map(|err| read_lines(err, sender)),
+        ];
+        if let Some(mut child_stdin) = child.stdin.take() {
+            // Hooks are free to exit without reading their input
+            let _ = child_stdin.write_all(stdin);
+        }
+        let mut output = String::new();
+        for line in receiver {
+            let _ = self.window.emit(
+                HOOK_OUTPUT_EVENT,
+                HookOutput {
+                    hook: hook.name().to_string(),
+                    line: line.clone(),
+                },
+            );
+            output.push_str(&line);
+            output.push('\n');
+        }
+        for reader in readers.into_iter().flatten() {
+            let _ = reader.join();
+        }
+
+        if child.wait()?.success() {
+            return Ok(());
+        }
+        Err(GitError::HookFailed {
+            hook: hook.name().to_string(),
+            output,
+        })
+    }
+
+    /// Hooks live in `core.hooksPath`, relative to the working tree when it
+    /// isn't absolute, or in the hooks directory of the repository.
+    fn hook_path(&self, hook: Hook) -> Option<PathBuf> {
+        let configured = self
+            .repo
+            .config()
+            .and_then(|config| config.get_path("core.hooksPath"));
+        let hooks_dir = match configured {
+            // Joining an absolute path replaces the working tree one
+            Ok(dir) => self.work_dir().join(dir),
+            Err(_) => self.repo.path().join("hooks"),
+        };
+        let path = hooks_dir.join(hook.name());
+        if is_executable(&path) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Hooks run from the root of the working tree, or the git directory of
+    /// bare repositories.
+    fn work_dir(&self) -> &Path {
+        self.repo.workdir().unwrap_or_else(|| self.repo.path())
+    }
+}
+
+fn read_lines<R: Read + Send + 'static>(
+    reader: R,
+    sender: mpsc::Sender<String>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = vec![];
+        while let Ok(read) = reader.read_until(b'\n', &mut line) {
+            if read == 0 {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            let _ = sender.send(text.trim_end_matches(&['\r', '\n'][..]).to_string());
+            line.clear();
+        }
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(unix)]
+fn hook_command(path: &Path) -> Command {
+    Command::new(path)
+}
+
+/// Hooks are shell scripts, Windows can only run them through the sh that
+/// comes with git.
+#[cfg(not(unix))]
+fn hook_command(path: &Path) -> Command {
+    let mut command = Command::new("sh");
+    command.arg(path);
+    command
+}
@@ -0,0 +1,53 @@
+use crate::error::GitError;
+use crate::git::Repo;
+use crate::state::{ImageData, ImageDiff};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::path::Path;
+
+/// Images bigger than this are described without sending their content.
+const MAX_IMAGE_SIZE: usize = 20 * 1024 * 1024;
+
+/// Mime type of the file if its extension is one of the supported images.
+pub fn image_mime(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "svg" => Some("image/svg+xml"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Both versions of an image, HEAD and index when `staged`, index and working
+/// tree otherwise. A side is missing when the file doesn't exist there.
+pub fn image_diff(repo: &Repo, path: &str, staged: bool) -> Result<ImageDiff, GitError> {
+    let mime = image_mime(path).ok_or(GitError::NotAnImage)?;
+    let (old, new) = repo.diff_sides(path, path, staged)?;
+    Ok(ImageDiff {
+        old: old.map(|(_, content)| image_data(mime, &content)),
+        new: new.map(|(_, content)| image_data(mime, &content)),
+    })
+}
+
+fn image_data(mime: &str, content: &[u8]) -> ImageData {
+    // imagesize doesn't read svg, the frontend can size those itself
+    let (width, height) = match imagesize::blob_size(content) {
+        Ok(size) => (Some(size.width), Some(size.height)),
+        Err(_) => (None, None),
+    };
+    let data = if content.len() <= MAX_IMAGE_SIZE {
+        Some(STANDARD.encode(content))
+    } else {
+        None
+    };
+    ImageData {
+        mime: mime.to_owned(),
+        size: content.len(),
+        width,
+        height,
+        data,
+    }
+}
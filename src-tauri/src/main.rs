@@ -7,36 +7,80 @@ use tauri::api::shell;
 use tauri::Manager;
 
 mod cmd;
+mod commit_message;
 mod db;
+mod diff;
 mod error;
 mod git;
+mod highlight;
+mod hooks;
+mod image_diff;
 mod menu;
+mod operations;
+mod progress;
+mod signing;
 mod state;
 mod utils;
+mod word_diff;
 
 fn main() {
     tauri::Builder::default()
         .manage(state::MyState::default())
         .invoke_handler(tauri::generate_handler![
             cmd::open,
+            cmd::create_operation,
+            cmd::cancel_operation,
+            cmd::clone_repo,
             cmd::find_branches,
             cmd::get_current_branch_name,
             cmd::get_repo_name,
             cmd::checkout_branch,
             cmd::get_remotes,
+            cmd::get_remote_details,
+            cmd::add_remote,
+            cmd::rename_remote,
+            cmd::remove_remote,
+            cmd::set_remote_url,
+            cmd::set_remote_push_url,
             cmd::fetch_remote,
             cmd::get_modified_files,
             cmd::get_repo_diff,
+            cmd::get_staged_file_stats,
+            cmd::get_unstaged_file_stats,
+            cmd::get_commit_file_stats,
+            cmd::read_commit_template,
+            cmd::write_commit_template,
+            cmd::read_commit_rules,
+            cmd::write_commit_rules,
+            cmd::validate_commit_message,
+            cmd::read_commit_draft,
+            cmd::write_commit_draft,
+            cmd::search_commit_messages,
             cmd::commit,
+            cmd::read_recent_identities,
+            cmd::verify_commit_signatures,
+            cmd::list_tags,
+            cmd::create_tag,
+            cmd::delete_tag,
+            cmd::delete_remote_tag,
+            cmd::push_tags,
             cmd::add_all,
             cmd::add,
             cmd::discard,
+            cmd::discard_selection,
             cmd::get_staged_files,
             cmd::get_all_repos,
             cmd::add_new_repo,
             cmd::write_last_opened_repo,
             cmd::read_last_opened_repo,
             cmd::git_diff,
+            cmd::git_diff_staged,
+            cmd::git_diff_unstaged,
+            cmd::git_diff_file,
+            cmd::git_diff_image,
+            cmd::diff_revisions,
+            cmd::read_diff_settings,
+            cmd::write_diff_settings,
             cmd::push_remote,
             cmd::get_pending_commits_to_push,
             cmd::get_pending_commits_to_pull,
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Long-running operations the UI can cancel, by id.
+#[derive(Default)]
+pub struct Operations {
+    next_id: AtomicU32,
+    running: Mutex<HashMap<u32, CancelToken>>,
+}
+
+/// Shared flag the operation checks between two steps of its work.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+}
+
+impl Operations {
+    /// Reserves an id to start an operation with, it can be cancelled even
+    /// before the operation starts.
+    pub fn create(&self) -> u32 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.running
+            .lock()
+            .unwrap()
+            .insert(id, CancelToken::default());
+        id
+    }
+
+    /// Returns false when no operation has this id, or it already finished.
+    /// The id is released, even if its operation never starts.
+    pub fn cancel(&self, id: u32) -> bool {
+        match self.running.lock().unwrap().remove(&id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registers an operation under `id`, operations without id can't be
+    /// cancelled. An id that isn't registered was cancelled before the
+    /// operation started, it stops right away. The id is released when the
+    /// guard is dropped.
+    pub fn start(&self, id: Option<u32>) -> OperationGuard<'_> {
+        let token = match id.map(|id| self.running.lock().unwrap().get(&id).cloned()) {
+            Some(Some(token)) => token,
+            Some(None) => {
+                let token = CancelToken::default();
+                token.cancel();
+                token
+            }
+            None => CancelToken::default(),
+        };
+        OperationGuard {
+            operations: self,
+            id,
+            token,
+        }
+    }
+}
+
+pub struct OperationGuard<'a> {
+    operations: &'a Operations,
+    id: Option<u32>,
+    pub token: CancelToken,
+}
+
+impl Drop for OperationGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.operations.running.lock().unwrap().remove(&id);
+        }
+    }
+}
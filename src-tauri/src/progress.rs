@@ -0,0 +1,174 @@
+use crate::error::GitError;
+use crate::operations::CancelToken;
+use crate::state::{
+    RefUpdate, RefUpdateKind, RemoteOperation, RemoteProgress, RemoteProgressEvent, TransferStats,
+};
+use git2::{Oid, RemoteCallbacks};
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+use tauri::Window;
+
+/// Event carrying the progress of fetches, pushes and clones.
+pub const REMOTE_PROGRESS_EVENT: &str = "remote-progress";
+
+/// Transfer progress is reported at most this often, the last one always is.
+const TRANSFER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sends what happens during a network operation on one remote to the window,
+/// keeps the references it updated and stops the transfer once cancelled.
+pub struct ProgressReporter {
+    window: Window,
+    cancel: CancelToken,
+    operation: RemoteOperation,
+    remote: String,
+    started: Instant,
+    last_transfer: Cell<Option<Instant>>,
+    updates: RefCell<Vec<RefUpdate>>,
+}
+
+impl ProgressReporter {
+    pub fn new(
+        window: &Window,
+        operation: RemoteOperation,
+        remote: &str,
+        cancel: CancelToken,
+    ) -> Self {
+        ProgressReporter {
+            window: window.clone(),
+            cancel,
+            operation,
+            remote: remote.to_string(),
+            started: Instant::now(),
+            last_transfer: Cell::new(None),
+            updates: RefCell::new(vec![]),
+        }
+    }
+
+    /// Reports the transfer, the messages of the server and the updated
+    /// references through `callbacks`.
+    pub fn attach<'a>(&'a self, callbacks: &mut RemoteCallbacks<'a>) {
+        callbacks.transfer_progress(move |stats| {
+            let done = stats.received_objects() == stats.total_objects()
+                && stats.indexed_deltas() == stats.total_deltas();
+            self.transfer(
+                TransferStats {
+                    received_objects: stats.received_objects(),
+                    indexed_objects: stats.indexed_objects(),
+                    total_objects: stats.total_objects(),
+                    indexed_deltas: stats.indexed_deltas(),
+                    total_deltas: stats.total_deltas(),
+                    bytes: stats.received_bytes(),
+                    bytes_per_second: self.rate(stats.received_bytes()),
+                },
+                done,
+            );
+            !self.cancel.is_cancelled()
+        });
+        callbacks.push_transfer_progress(move |current, total, bytes| {
+            self.transfer(
+                TransferStats {
+                    received_objects: current,
+                    indexed_objects: current,
+                    total_objects: total,
+                    indexed_deltas: 0,
+                    total_deltas: 0,
+                    bytes,
+                    bytes_per_second: self.rate(bytes),
+                },
+                current == total,
+            );
+        });
+        callbacks.sideband_progress(move |data| {
+            let message = String::from_utf8_lossy(data);
+            // Servers redraw their progress lines with carriage returns
+            for line in message.split(&['\r', '\n'][..]) {
+                if !line.trim().is_empty() {
+                    self.emit(RemoteProgress::Sideband(line.to_string()));
+                }
+            }
+            !self.cancel.is_cancelled()
+        });
+        callbacks.update_tips(move |refname, old, new| {
+            self.ref_update(refname, old, new);
+            !self.cancel.is_cancelled()
+        });
+    }
+
+    /// For the steps between two transfers, a push can only be stopped
+    /// before its pack is sent.
+    pub fn check_cancelled(&self) -> Result<(), GitError> {
+        if self.cancel.is_cancelled() {
+            return Err(GitError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Callbacks stop a transfer with a user error, told apart from others by
+    /// the cancel flag.
+    pub fn error(&self, err: git2::Error) -> GitError {
+        if self.cancel.is_cancelled() {
+            GitError::Cancelled
+        } else {
+            err.into()
+        }
+    }
+
+    /// References updated so far, in the order they were.
+    pub fn take_updates(&self) -> Vec<RefUpdate> {
+        self.updates.take()
+    }
+
+    /// Pruned references are reported with a zero new id.
+    fn ref_update(&self, refname: &str, old: Oid, new: Oid) {
+        let kind = if old.is_zero() {
+            RefUpdateKind::New
+        } else if new.is_zero() {
+            RefUpdateKind::Deleted
+        } else {
+            RefUpdateKind::Updated
+        };
+        let update = RefUpdate {
+            refname: refname.to_string(),
+            kind,
+            old: Some(old)
+                .filter(|oid| !oid.is_zero())
+                .map(|oid| oid.to_string()),
+            new: Some(new)
+                .filter(|oid| !oid.is_zero())
+                .map(|oid| oid.to_string()),
+        };
+        self.emit(RemoteProgress::RefUpdate(update.clone()));
+        self.updates.borrow_mut().push(update);
+    }
+
+    fn transfer(&self, stats: TransferStats, done: bool) {
+        let now = Instant::now();
+        if let Some(last) = self.last_transfer.get() {
+            if !done && now.duration_since(last) < TRANSFER_INTERVAL {
+                return;
+            }
+        }
+        self.last_transfer.set(Some(now));
+        self.emit(RemoteProgress::Transfer(stats));
+    }
+
+    fn rate(&self, bytes: usize) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            bytes as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn emit(&self, progress: RemoteProgress) {
+        let _ = self.window.emit(
+            REMOTE_PROGRESS_EVENT,
+            RemoteProgressEvent {
+                operation: self.operation,
+                remote: self.remote.clone(),
+                progress,
+            },
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use crate::error::GitError;
+use crate::state::{CommitSignature, SignatureStatus};
+use git2::{Config, ErrorCode, Oid, Repository, Signature};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+pub enum SigningFormat {
+    OpenPgp,
+    Ssh,
+    X509,
+}
+
+/// How objects get signed, read from `gpg.format`, `user.signingkey` and the
+/// program configured for the format.
+pub struct SigningConfig {
+    pub format: SigningFormat,
+    pub key: Option<String>,
+    pub program: String,
+}
+
+impl SigningConfig {
+    pub fn from_config(config: &Config) -> Result<Self, GitError> {
+        let format = match config.get_string("gpg.format").ok().as_deref() {
+            None | Some("openpgp") => SigningFormat::OpenPgp,
+            Some("ssh") => SigningFormat::Ssh,
+            Some("x509") => SigningFormat::X509,
+            Some(other) => {
+                return Err(GitError::SigningFailed(format!(
+                    "unsupported gpg.format {}",
+                    other
+                )))
+            }
+        };
+        let program = signing_program(config, &format);
+        Ok(SigningConfig {
+            format,
+            key: config.get_string("user.signingkey").ok(),
+            program,
+        })
+    }
+
+    /// Detached signature of `buffer`, made by the configured program with
+    /// the signing key or else the `signer` identity.
+    pub fn sign(&self, buffer: &str, signer: &Signature) -> Result<String, GitError> {
+        match self.format {
+            SigningFormat::OpenPgp | SigningFormat::X509 => {
+                let key = match &self.key {
+                    Some(key) => key.clone(),
+                    None => format!(
+                        "{} <{}>",
+                        signer.name().unwrap_or_default(),
+                        signer.email().unwrap_or_default()
+                    ),
+                };
+                let mut command = Command::new(&self.program);
+                command.args(["--status-fd=2", "-bsau", &key]);
+                run_signing_program(command, buffer)
+            }
+            SigningFormat::Ssh => self.sign_ssh(buffer),
+        }
+    }
+
+    /// ssh-keygen reads the data to sign from a file and writes the
+    /// signature next to it, the same as git does.
+    fn sign_ssh(&self, buffer: &str) -> Result<String, GitError> {
+        let key = self.key.as_ref().ok_or_else(|| {
+            GitError::SigningFailed("user.signingkey is needed to sign with ssh".to_string())
+        })?;
+        let dir = tempfile::tempdir()?;
+        let mut command = Command::new(&self.program);
+        command.args(["-Y", "sign", "-n", "git", "-f"]);
+        // A literal public key means the private one lives in the ssh agent
+        let literal_key = key.strip_prefix("key::").or_else(|| {
+            if key.starts_with("ssh-") {
+                Some(key.as_str())
+            } else {
+                None
+            }
+        });
+        match literal_key {
+            Some(public_key) => {
+                let key_path = dir.path().join("signing_key.pub");
+                fs::write(&key_path, public_key)?;
+                command.arg(&key_path).arg("-U");
+            }
+            None => {
+                command.arg(expand_home(key));
+            }
+        }
+        let buffer_path = dir.path().join("buffer");
+        fs::write(&buffer_path, buffer)?;
+        command.arg(&buffer_path);
+        run_signing_program(command, "")?;
+        let signature = fs::read_to_string(dir.path().join("buffer.sig"))?;
+        Ok(signature)
+    }
+}
+
+/// Program configured for `format`, or the one git uses by default.
+fn signing_program(config: &Config, format: &SigningFormat) -> String {
+    let (program_key, default_program) = match format {
+        SigningFormat::OpenPgp => ("gpg.openpgp.program", "gpg"),
+        SigningFormat::Ssh => ("gpg.ssh.program", "ssh-keygen"),
+        SigningFormat::X509 => ("gpg.x509.program", "gpgsm"),
+    };
+    config
+        .get_string(program_key)
+        .or_else(|_| match format {
+            // gpg.program is the older name of the openpgp one
+            SigningFormat::OpenPgp => config.get_string("gpg.program"),
+            _ => Err(git2::Error::from_str("not set")),
+        })
+        .unwrap_or_else(|_| default_program.to_string())
+}
+
+/// Whether `commit.gpgsign` asks for commits to be signed.
+pub fn should_sign_commits(config: &Config) -> bool {
+    config.get_bool("commit.gpgsign").unwrap_or(false)
+}
+
+/// Whether `tag.gpgSign` asks for annotated tags to be signed.
+pub fn should_sign_tags(config: &Config) -> bool {
+    config.get_bool("tag.gpgSign").unwrap_or(false)
+}
+
+/// Runs the signing program feeding `input` through stdin, its stdout is the
+/// signature and its stderr the error shown when it fails.
+fn run_signing_program(command: Command, input: &str) -> Result<String, GitError> {
+    let output = run_program(command, input.as_bytes())?;
+    if !output.status.success() {
+        return Err(GitError::SigningFailed(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn run_program(mut command: Command, input: &[u8]) -> Result<Output, GitError> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            GitError::SigningFailed(format!("can't run {:?}: {}", command.get_program(), e))
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    Ok(child.wait_with_output()?)
+}
+
+/// Checks the signature of a commit with gpg, gpgsm or ssh-keygen depending
+/// on its kind. ssh signatures are trusted through `gpg.ssh.allowedSignersFile`.
+pub fn verify_commit(repo: &Repository, oid: Oid) -> Result<CommitSignature, GitError> {
+    let (signature, signed_data) = match repo.extract_signature(&oid, None) {
+        Ok(parts) => parts,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            return Ok(CommitSignature {
+                commit: oid.to_string(),
+                status: SignatureStatus::Unsigned,
+                signer: None,
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let signature = signature.as_str().ok_or(GitError::InvalidCommit)?;
+    let config = repo.config()?;
+    let (status, signer) = if signature.starts_with("-----BEGIN SSH SIGNATURE-----") {
+        verify_ssh(&config, signature, &signed_data)?
+    } else if signature.starts_with("-----BEGIN SIGNED MESSAGE-----") {
+        let program = signing_program(&config, &SigningFormat::X509);
+        verify_gpg(&program, signature, &signed_data)?
+    } else {
+        let program = signing_program(&config, &SigningFormat::OpenPgp);
+        verify_gpg(&program, signature, &signed_data)?
+    };
+    Ok(CommitSignature {
+        commit: oid.to_string(),
+        status,
+        signer,
+    })
+}
+
+/// Reads the verdict from the `--status-fd` lines of gpg and gpgsm.
+fn verify_gpg(
+    program: &str,
+    signature: &str,
+    signed_data: &[u8],
+) -> Result<(SignatureStatus, Option<String>), GitError> {
+    let dir = tempfile::tempdir()?;
+    let signature_path = dir.path().join("signature");
+    fs::write(&signature_path, signature)?;
+    let mut command = Command::new(program);
+    command
+        .args(["--status-fd=1", "--verify"])
+        .arg(&signature_path)
+        .arg("-");
+    let output = run_program(command, signed_data)?;
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut parts = line.trim_start_matches("[GNUPG:] ").splitn(3, ' ');
+        let (keyword, key, user) = (parts.next(), parts.next(), parts.next());
+        let status = match keyword {
+            Some("GOODSIG") => SignatureStatus::Good,
+            Some("BADSIG") | Some("EXPKEYSIG") | Some("REVKEYSIG") => SignatureStatus::Bad,
+            Some("ERRSIG") | Some("NO_PUBKEY") => {
+                return Ok((SignatureStatus::UnknownKey, key.map(str::to_string)))
+            }
+            _ => continue,
+        };
+        return Ok((status, user.or(key).map(str::to_string)));
+    }
+    Ok((SignatureStatus::Bad, None))
+}
+
+/// Looks the signer up in the allowed signers file and verifies the signature
+/// for it, signatures from keys not in the file are reported as unknown.
+fn verify_ssh(
+    config: &Config,
+    signature: &str,
+    signed_data: &[u8],
+) -> Result<(SignatureStatus, Option<String>), GitError> {
+    let program = signing_program(config, &SigningFormat::Ssh);
+    let dir = tempfile::tempdir()?;
+    let signature_path = dir.path().join("signature");
+    fs::write(&signature_path, signature)?;
+
+    if let Ok(allowed_signers) = config.get_path("gpg.ssh.allowedSignersFile") {
+        let mut command = Command::new(&program);
+        command
+            .args(["-Y", "find-principals", "-f"])
+            .arg(&allowed_signers)
+            .arg("-s")
+            .arg(&signature_path);
+        let output = run_program(command, b"")?;
+        let principals = String::from_utf8_lossy(&output.stdout);
+        if let (true, Some(principal)) = (output.status.success(), principals.lines().next()) {
+            let mut command = Command::new(&program);
+            command
+                .args(["-Y", "verify", "-n", "git", "-f"])
+                .arg(&allowed_signers)
+                .args(["-I", principal, "-s"])
+                .arg(&signature_path);
+            let output = run_program(command, signed_data)?;
+            let status = if output.status.success() {
+                SignatureStatus::Good
+            } else {
+                SignatureStatus::Bad
+            };
+            return Ok((status, Some(principal.to_string())));
+        }
+    }
+
+    let mut command = Command::new(&program);
+    command
+        .args(["-Y", "check-novalidate", "-n", "git", "-s"])
+        .arg(&signature_path);
+    let output = run_program(command, signed_data)?;
+    let status = if output.status.success() {
+        SignatureStatus::UnknownKey
+    } else {
+        SignatureStatus::Bad
+    };
+    Ok((status, None))
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
@@ -1,10 +1,14 @@
 use crate::git::Repo;
+use crate::highlight::HighlightCache;
+use crate::operations::Operations;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 
 #[derive(Default)]
 pub struct MyState {
     pub repo: Arc<Mutex<Option<Repo>>>,
+    pub highlight_cache: Arc<HighlightCache>,
+    pub operations: Operations,
 }
 
 pub type AppArg<'a> = tauri::State<'a, MyState>;
@@ -15,6 +19,12 @@ pub enum MyBranchType {
     Remote,
 }
 
+#[derive(Deserialize, Debug)]
+pub enum DiscardSource {
+    Head,
+    Index,
+}
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct FileStatus {
@@ -32,9 +42,363 @@ pub struct Stats {
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct GitDiff {
-    pub diff_content: String,
-    pub new_line: Option<u32>,
+pub struct FileStats {
+    pub path: String,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub binary: bool,
+}
+
+/// `TwoDot` compares both revisions directly, `ThreeDot` compares `to` with
+/// its merge base with `from`, showing only what `to` introduced.
+#[derive(Deserialize, Debug)]
+pub enum RevisionRange {
+    TwoDot,
+    ThreeDot,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionDiff {
+    pub stats: Stats,
+    pub files: Vec<DiffFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum WhitespaceMode {
+    None,
+    IgnoreAll,
+    IgnoreChange,
+    IgnoreEol,
+}
+
+// libgit2 has no histogram implementation
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DiffAlgorithm {
+    Myers,
+    Minimal,
+    Patience,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffSettings {
+    pub whitespace: WhitespaceMode,
+    pub context_lines: u32,
+    pub algorithm: DiffAlgorithm,
+    pub detect_renames: bool,
+    pub detect_copies: bool,
+    pub similarity_threshold: u16,
+}
+
+impl Default for DiffSettings {
+    fn default() -> Self {
+        DiffSettings {
+            whitespace: WhitespaceMode::None,
+            context_lines: 3,
+            algorithm: DiffAlgorithm::Myers,
+            detect_renames: true,
+            detect_copies: false,
+            similarity_threshold: 50,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub enum DiffFileStatus {
+    Unmodified,
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+    Ignored,
+    Untracked,
+    Typechange,
+    Unreadable,
+    Conflicted,
+}
+
+impl From<git2::Delta> for DiffFileStatus {
+    fn from(delta: git2::Delta) -> Self {
+        match delta {
+            git2::Delta::Unmodified => DiffFileStatus::Unmodified,
+            git2::Delta::Added => DiffFileStatus::Added,
+            git2::Delta::Deleted => DiffFileStatus::Deleted,
+            git2::Delta::Modified => DiffFileStatus::Modified,
+            git2::Delta::Renamed => DiffFileStatus::Renamed,
+            git2::Delta::Copied => DiffFileStatus::Copied,
+            git2::Delta::Ignored => DiffFileStatus::Ignored,
+            git2::Delta::Untracked => DiffFileStatus::Untracked,
+            git2::Delta::Typechange => DiffFileStatus::Typechange,
+            git2::Delta::Unreadable => DiffFileStatus::Unreadable,
+            git2::Delta::Conflicted => DiffFileStatus::Conflicted,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffFile {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub status: DiffFileStatus,
+    pub old_mode: u32,
+    pub new_mode: u32,
+    pub binary: bool,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub encoding: String,
+    pub image: bool,
+    pub truncated: bool,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<DiffHunk>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffHunk {
+    pub header: String,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffLine {
+    pub content: String,
     pub old_line: Option<u32>,
+    pub new_line: Option<u32>,
     pub origin: char,
+    /// Character ranges that changed compared to the paired added/deleted line
+    pub changes: Vec<LineRange>,
+    /// Syntax highlighting spans, only filled when requested
+    pub highlight: Option<Vec<HighlightSpan>>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub scope: String,
+}
+
+#[derive(Serialize)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffLineId {
+    pub old_line: Option<u32>,
+    pub new_line: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub enum DiscardSelection {
+    Hunks(Vec<usize>),
+    Lines(Vec<DiffLineId>),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageData {
+    pub mime: String,
+    pub size: usize,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    /// Base64 encoded content, missing for images too big to send
+    pub data: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImageDiff {
+    pub old: Option<ImageData>,
+    pub new: Option<ImageData>,
+}
+
+#[derive(Serialize, Debug)]
+pub enum SignatureStatus {
+    Good,
+    Bad,
+    UnknownKey,
+    Unsigned,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitSignature {
+    pub commit: String,
+    pub status: SignatureStatus,
+    /// Key owner for gpg signatures, principal for ssh ones
+    pub signer: Option<String>,
+}
+
+/// One line printed by a hook while it runs.
+#[derive(Serialize, Clone)]
+pub struct HookOutput {
+    pub hook: String,
+    pub line: String,
+}
+
+/// Someone credited on a commit, as author or co-author.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} <{}>", self.name, self.email)
+    }
+}
+
+/// Checks every commit message goes through, all of them off by default.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitRules {
+    /// Subjects must read `type(scope)!: description`
+    pub conventional: bool,
+    /// Types allowed in conventional subjects, any type when empty
+    pub types: Vec<String>,
+    /// Scopes allowed in conventional subjects, any scope when empty
+    pub scopes: Vec<String>,
+    pub max_subject_length: Option<usize>,
+    pub blank_second_line: bool,
+    /// Regex the message has to match somewhere, like `[A-Z]+-[0-9]+`
+    pub issue_key_pattern: Option<String>,
+}
+
+impl Default for CommitRules {
+    fn default() -> Self {
+        CommitRules {
+            conventional: false,
+            types: [
+                "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore",
+                "revert",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+            scopes: vec![],
+            max_subject_length: None,
+            blank_second_line: false,
+            issue_key_pattern: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum CommitRule {
+    Conventional,
+    Type,
+    Scope,
+    SubjectLength,
+    BlankSecondLine,
+    IssueKey,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitViolation {
+    pub rule: CommitRule,
+    /// Line of the message breaking the rule, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TagInfo {
+    pub name: String,
+    /// Object the tag ends up pointing to, usually a commit
+    pub target: String,
+    /// `commit`, `tree` or `blob`, unknown when the object can't be read
+    pub target_kind: Option<String>,
+    pub annotated: bool,
+    pub tagger: Option<Identity>,
+    /// Seconds since the epoch the annotated tag was made at
+    pub time: Option<i64>,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteInfo {
+    pub name: String,
+    pub url: Option<String>,
+    /// Only set when pushes go somewhere else than `url`
+    pub push_url: Option<String>,
+    pub fetch_refspecs: Vec<String>,
+    pub push_refspecs: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub enum RefUpdateKind {
+    New,
+    Updated,
+    Deleted,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RefUpdate {
+    pub refname: String,
+    pub kind: RefUpdateKind,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// References a fetch changed on one remote.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchSummary {
+    pub remote: String,
+    pub updates: Vec<RefUpdate>,
+    /// Why the remote couldn't be fetched, when fetching every remote
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub enum RemoteOperation {
+    Fetch,
+    Push,
+    Clone,
+}
+
+/// Objects and bytes sent or received so far, deltas are only resolved when
+/// receiving.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferStats {
+    pub received_objects: usize,
+    pub indexed_objects: usize,
+    pub total_objects: usize,
+    pub indexed_deltas: usize,
+    pub total_deltas: usize,
+    pub bytes: usize,
+    pub bytes_per_second: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub enum RemoteProgress {
+    Transfer(TransferStats),
+    /// A line the server printed, like its counting and compressing steps
+    Sideband(String),
+    RefUpdate(RefUpdate),
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteProgressEvent {
+    pub operation: RemoteOperation,
+    pub remote: String,
+    pub progress: RemoteProgress,
 }
@@ -0,0 +1,224 @@
+use crate::state::{DiffLine, LineRange};
+
+/// Lines split into more tokens than this are not compared word by word.
+const MAX_TOKENS: usize = 500;
+
+/// Pairs every deleted line with the added line at the same position of the
+/// block that replaces it, and fills both with the character ranges that
+/// differ between them.
+pub fn highlight_hunk(lines: &mut [DiffLine]) {
+    let mut idx = 0;
+    while idx < lines.len() {
+        if lines[idx].origin != '-' {
+            idx += 1;
+            continue;
+        }
+        let deleted_start = idx;
+        while idx < lines.len() && lines[idx].origin == '-' {
+            idx += 1;
+        }
+        let added_start = idx;
+        while idx < lines.len() && lines[idx].origin == '+' {
+            idx += 1;
+        }
+        let pairs = (added_start - deleted_start).min(idx - added_start);
+        for pair in 0..pairs {
+            let (old_changes, new_changes) = changed_ranges(
+                &lines[deleted_start + pair].content,
+                &lines[added_start + pair].content,
+            );
+            lines[deleted_start + pair].changes = old_changes;
+            lines[added_start + pair].changes = new_changes;
+        }
+    }
+}
+
+/// Character ranges of `old` and `new` that aren't part of their longest
+/// common sequence of words.
+fn changed_ranges(old: &str, new: &str) -> (Vec<LineRange>, Vec<LineRange>) {
+    let old_tokens = tokenize(old.trim_end_matches(&['\r', '\n'][..]));
+    let new_tokens = tokenize(new.trim_end_matches(&['\r', '\n'][..]));
+    if old_tokens.len() > MAX_TOKENS || new_tokens.len() > MAX_TOKENS {
+        return (vec![], vec![]);
+    }
+
+    // lcs[i][j] is the length of the common sequence of old[i..] and new[j..]
+    let width = new_tokens.len() + 1;
+    let mut lcs = vec![0u32; (old_tokens.len() + 1) * width];
+    for i in (0..old_tokens.len()).rev() {
+        for j in (0..new_tokens.len()).rev() {
+            lcs[i * width + j] = if old_tokens[i].text == new_tokens[j].text {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut old_changes = vec![];
+    let mut new_changes = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old_tokens.len() || j < new_tokens.len() {
+        if i < old_tokens.len() && j < new_tokens.len() && old_tokens[i].text == new_tokens[j].text
+        {
+            i += 1;
+            j += 1;
+        } else if j == new_tokens.len()
+            || (i < old_tokens.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            push_range(&mut old_changes, &old_tokens[i]);
+            i += 1;
+        } else {
+            push_range(&mut new_changes, &new_tokens[j]);
+            j += 1;
+        }
+    }
+    (old_changes, new_changes)
+}
+
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+/// Splits a line into runs of word characters, runs of whitespace and single
+/// punctuation characters. Offsets are counted in characters.
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut token_start: Option<(usize, usize, CharClass)> = None;
+    let mut char_idx = 0;
+    for (byte_idx, c) in line.char_indices() {
+        let class = CharClass::of(c);
+        if let Some((start_byte, start_char, current)) = token_start {
+            if current != class || class == CharClass::Punctuation {
+                tokens.push(Token {
+                    text: &line[start_byte..byte_idx],
+                    start: start_char,
+                    end: char_idx,
+                });
+                token_start = Some((byte_idx, char_idx, class));
+            }
+        } else {
+            token_start = Some((byte_idx, char_idx, class));
+        }
+        char_idx += 1;
+    }
+    if let Some((start_byte, start_char, _)) = token_start {
+        tokens.push(Token {
+            text: &line[start_byte..],
+            start: start_char,
+            end: char_idx,
+        });
+    }
+    tokens
+}
+
+/// Extends the last range when the token follows it right away.
+fn push_range(ranges: &mut Vec<LineRange>, token: &Token) {
+    if let Some(last) = ranges.last_mut() {
+        if last.end == token.start {
+            last.end = token.end;
+            return;
+        }
+    }
+    ranges.push(LineRange {
+        start: token.start,
+        end: token.end,
+    });
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum CharClass {
+    Word,
+    Whitespace,
+    Punctuation,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        if c.is_alphanumeric() || c == '_' {
+            CharClass::Word
+        } else if c.is_whitespace() {
+            CharClass::Whitespace
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(origin: char, content: &str) -> DiffLine {
+        DiffLine {
+            content: content.to_string(),
+            old_line: None,
+            new_line: None,
+            origin,
+            changes: vec![],
+            highlight: None,
+        }
+    }
+
+    fn ranges(ranges: &[LineRange]) -> Vec<(usize, usize)> {
+        ranges
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect()
+    }
+
+    #[test]
+    fn marks_changed_words_of_paired_lines() {
+        let mut lines = vec![line('-', "let a = 1;\n"), line('+', "let b = 1;\n")];
+        highlight_hunk(&mut lines);
+        assert_eq!(ranges(&lines[0].changes), vec![(4, 5)]);
+        assert_eq!(ranges(&lines[1].changes), vec![(4, 5)]);
+    }
+
+    #[test]
+    fn pairs_lines_by_position_in_unequal_blocks() {
+        let mut lines = vec![
+            line('-', "one\n"),
+            line('-', "two\n"),
+            line('+', "uno\n"),
+            line(' ', "same\n"),
+            line('-', "three\n"),
+            line('+', "tres\n"),
+            line('+', "extra\n"),
+        ];
+        highlight_hunk(&mut lines);
+        let changes: Vec<_> = lines.iter().map(|line| ranges(&line.changes)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                vec![(0, 3)],
+                vec![],
+                vec![(0, 3)],
+                vec![],
+                vec![(0, 5)],
+                vec![(0, 4)],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_offsets_in_characters() {
+        let (old, new) = changed_ranges("héllo wörld", "héllo world");
+        assert_eq!(ranges(&old), vec![(6, 11)]);
+        assert_eq!(ranges(&new), vec![(6, 11)]);
+    }
+
+    #[test]
+    fn skips_lines_with_too_many_tokens() {
+        // "a " is two tokens
+        let at_limit = "a ".repeat(MAX_TOKENS / 2);
+        let over_limit = format!("{}a", at_limit);
+        let (old, new) = changed_ranges(&at_limit, "b");
+        assert!(!old.is_empty() && !new.is_empty());
+        let (old, new) = changed_ranges(&over_limit, "b");
+        assert!(old.is_empty() && new.is_empty());
+    }
+}
@@ -1,4 +1,9 @@
-import type { Repos } from "src/shared/types";
+import type {
+  CommitRules,
+  DiffSettings,
+  Identity,
+  Repos,
+} from "src/shared/types";
 
 import { invoke } from "@tauri-apps/api";
 
@@ -31,3 +36,31 @@ export function addNewRepoToDb(
 export function getAllReposFromDb(): Promise<Repos[]> {
   return invoke("get_all_repos");
 }
+
+export function getDiffSettingsFromDb(): Promise<DiffSettings> {
+  return invoke<DiffSettings>("read_diff_settings");
+}
+
+export function setDiffSettingsToDb(settings: DiffSettings): Promise<void> {
+  return invoke<void>("write_diff_settings", { settings });
+}
+
+export function getRecentIdentitiesFromDb(): Promise<Identity[]> {
+  return invoke<Identity[]>("read_recent_identities");
+}
+
+export function getCommitTemplateFromDb(): Promise<string | null> {
+  return invoke<string | null>("read_commit_template");
+}
+
+export function setCommitTemplateToDb(template: string | null): Promise<void> {
+  return invoke<void>("write_commit_template", { template });
+}
+
+export function getCommitRulesFromDb(): Promise<CommitRules> {
+  return invoke<CommitRules>("read_commit_rules");
+}
+
+export function setCommitRulesToDb(rules: CommitRules): Promise<void> {
+  return invoke<void>("write_commit_rules", { rules });
+}
@@ -1,6 +1,26 @@
-import type { FileStatus, GitDiff, RepoDiffStats } from "src/shared/types";
+import type {
+  CommitSignature,
+  CommitViolation,
+  DiffFile,
+  DiscardSelection,
+  DiscardSource,
+  FetchSummary,
+  FileStats,
+  FileStatus,
+  HookOutput,
+  Identity,
+  ImageDiff,
+  RemoteInfo,
+  RemoteProgressEvent,
+  RepoDiffStats,
+  RevisionDiff,
+  RevisionRange,
+  TagInfo,
+} from "src/shared/types";
+import type { UnlistenFn } from "@tauri-apps/api/event";
 
 import { invoke } from "@tauri-apps/api";
+import { listen } from "@tauri-apps/api/event";
 
 export function addAllFiles(): Promise<void> {
   return invoke("add_all");
@@ -10,18 +30,85 @@ export function addFiles(files: string[]): Promise<void> {
   return invoke("add", { files });
 }
 
-export function discardFiles(files: string[]): Promise<void> {
-  return invoke("discard", { files });
+export function discardFiles(
+  files: string[],
+  source: DiscardSource = "Index",
+  toTrash = false
+): Promise<void> {
+  return invoke("discard", { files, source, toTrash });
 }
 
-export function commit(message: string): Promise<void> {
-  return invoke("commit", { message });
+export function discardSelection(
+  file: string,
+  selection: DiscardSelection
+): Promise<void> {
+  return invoke("discard_selection", { file, selection });
 }
 
-export function getGitDiff(): Promise<GitDiff[]> {
+export function getCommitDraft(): Promise<string | null> {
+  return invoke("read_commit_draft");
+}
+
+export function setCommitDraft(message: string | null): Promise<void> {
+  return invoke("write_commit_draft", { message });
+}
+
+export function searchCommitMessages(
+  query?: string,
+  limit?: number
+): Promise<string[]> {
+  return invoke("search_commit_messages", { query, limit });
+}
+
+export function validateCommitMessage(
+  message: string
+): Promise<CommitViolation[]> {
+  return invoke("validate_commit_message", { message });
+}
+
+export function commit(
+  message: string,
+  author?: Identity,
+  coAuthors: Identity[] = [],
+  skipHooks = false
+): Promise<void> {
+  return invoke("commit", { message, author, coAuthors, skipHooks });
+}
+
+export function getGitDiff(): Promise<DiffFile[]> {
   return invoke("git_diff");
 }
 
+export function getStagedDiff(path?: string): Promise<DiffFile[]> {
+  return invoke("git_diff_staged", { path });
+}
+
+export function getUnstagedDiff(path?: string): Promise<DiffFile[]> {
+  return invoke("git_diff_unstaged", { path });
+}
+
+export function getFileDiff(
+  path: string,
+  staged = false,
+  maxLines?: number,
+  highlight = false
+): Promise<DiffFile | null> {
+  return invoke("git_diff_file", { path, staged, maxLines, highlight });
+}
+
+export function getImageDiff(path: string, staged = false): Promise<ImageDiff> {
+  return invoke("git_diff_image", { path, staged });
+}
+
+export function diffRevisions(
+  from: string,
+  to: string,
+  range: RevisionRange = "TwoDot",
+  paths?: string[]
+): Promise<RevisionDiff> {
+  return invoke("diff_revisions", { from, to, range, paths });
+}
+
 export function getModifiedFiles(): Promise<FileStatus[]> {
   return invoke("get_modified_files");
 }
@@ -34,6 +121,34 @@ export function getRepoDiff(): Promise<RepoDiffStats> {
   return invoke("get_repo_diff");
 }
 
+export function getStagedFileStats(): Promise<FileStats[]> {
+  return invoke("get_staged_file_stats");
+}
+
+export function getUnstagedFileStats(): Promise<FileStats[]> {
+  return invoke("get_unstaged_file_stats");
+}
+
+export function getCommitFileStats(commit: string): Promise<FileStats[]> {
+  return invoke("get_commit_file_stats", { commit });
+}
+
+export function createOperation(): Promise<number> {
+  return invoke("create_operation");
+}
+
+export function cancelOperation(id: number): Promise<boolean> {
+  return invoke("cancel_operation", { id });
+}
+
+export function cloneRepo(
+  url: string,
+  path: string,
+  operationId?: number
+): Promise<string> {
+  return invoke("clone_repo", { url, path, operationId });
+}
+
 export function openRepo(path: string): Promise<void> {
   return invoke("open", { path });
 }
@@ -70,14 +185,109 @@ export function getRemotes(): Promise<string[]> {
   return invoke("get_remotes");
 }
 
-export function fetchRemote(): Promise<void> {
-  return invoke("fetch_remote");
+export function getRemoteDetails(): Promise<RemoteInfo[]> {
+  return invoke("get_remote_details");
+}
+
+export function addRemote(
+  name: string,
+  url: string,
+  fetchRefspec?: string
+): Promise<void> {
+  return invoke("add_remote", { name, url, fetchRefspec });
+}
+
+export function renameRemote(name: string, newName: string): Promise<string[]> {
+  return invoke("rename_remote", { name, newName });
+}
+
+export function removeRemote(name: string): Promise<void> {
+  return invoke("remove_remote", { name });
+}
+
+export function setRemoteUrl(name: string, url: string): Promise<void> {
+  return invoke("set_remote_url", { name, url });
+}
+
+export function setRemotePushUrl(name: string, url?: string): Promise<void> {
+  return invoke("set_remote_push_url", { name, url });
+}
+
+export function fetchRemote(
+  remote?: string,
+  all = false,
+  prune?: boolean,
+  operationId?: number
+): Promise<FetchSummary[]> {
+  return invoke("fetch_remote", { remote, all, prune, operationId });
+}
+
+export function pullRemote(
+  skipHooks = false,
+  operationId?: number
+): Promise<void> {
+  return invoke("pull_from_remote", { skipHooks, operationId });
+}
+
+export function pushRemote(
+  skipHooks = false,
+  operationId?: number
+): Promise<void> {
+  return invoke("push_remote", { skipHooks, operationId });
+}
+
+export function onRemoteProgress(
+  handler: (event: RemoteProgressEvent) => void
+): Promise<UnlistenFn> {
+  return listen<RemoteProgressEvent>("remote-progress", (event) =>
+    handler(event.payload)
+  );
+}
+
+export function onHookOutput(
+  handler: (output: HookOutput) => void
+): Promise<UnlistenFn> {
+  return listen<HookOutput>("hook-output", (event) => handler(event.payload));
+}
+
+export function verifyCommitSignatures(
+  commits: string[]
+): Promise<CommitSignature[]> {
+  return invoke("verify_commit_signatures", { commits });
+}
+
+export function listTags(): Promise<TagInfo[]> {
+  return invoke("list_tags");
+}
+
+export function createTag(
+  name: string,
+  target?: string,
+  message?: string,
+  sign?: boolean,
+  force = false
+): Promise<void> {
+  return invoke("create_tag", { name, target, message, sign, force });
+}
+
+export function deleteTag(name: string): Promise<void> {
+  return invoke("delete_tag", { name });
 }
 
-export function pullRemote(): Promise<void> {
-  return invoke("pull_from_remote");
+export function deleteRemoteTag(
+  name: string,
+  remote?: string,
+  skipHooks = false,
+  operationId?: number
+): Promise<void> {
+  return invoke("delete_remote_tag", { name, remote, skipHooks, operationId });
 }
 
-export function pushRemote(): Promise<void> {
-  return invoke("push_remote");
+export function pushTags(
+  remote?: string,
+  tag?: string,
+  skipHooks = false,
+  operationId?: number
+): Promise<void> {
+  return invoke("push_tags", { remote, tag, skipHooks, operationId });
 }
@@ -9,11 +9,63 @@ export interface Repos {
   name: string;
 }
 
-export interface GitDiff {
-  diffContent: string;
+export type DiffFileStatus =
+  | "Unmodified"
+  | "Added"
+  | "Deleted"
+  | "Modified"
+  | "Renamed"
+  | "Copied"
+  | "Ignored"
+  | "Untracked"
+  | "Typechange"
+  | "Unreadable"
+  | "Conflicted";
+
+export interface LineRange {
+  start: number;
+  end: number;
+}
+
+export interface HighlightSpan {
+  start: number;
+  end: number;
+  scope: string;
+}
+
+export interface DiffLine {
+  content: string;
   newLine: number | null;
   oldLine: number | null;
   origin: string;
+  changes: LineRange[];
+  highlight: HighlightSpan[] | null;
+}
+
+export interface DiffHunk {
+  header: string;
+  oldStart: number;
+  oldLines: number;
+  newStart: number;
+  newLines: number;
+  lines: DiffLine[];
+}
+
+export interface DiffFile {
+  oldPath: string | null;
+  newPath: string | null;
+  status: DiffFileStatus;
+  oldMode: number;
+  newMode: number;
+  binary: boolean;
+  oldSize: number;
+  newSize: number;
+  encoding: string;
+  image: boolean;
+  truncated: boolean;
+  insertions: number;
+  deletions: number;
+  hunks: DiffHunk[];
 }
 
 export interface FileStatus {
@@ -27,8 +79,154 @@ export interface FileStatusWithStatusLabel {
   selected: boolean;
 }
 
+export interface FileStats {
+  path: string;
+  insertions: number;
+  deletions: number;
+  binary: boolean;
+}
+
 export type RepoDiffStats = {
   deletions: number;
   filesChanged: number;
   insertions: number;
 };
+
+export interface DiffLineId {
+  oldLine: number | null;
+  newLine: number | null;
+}
+
+export type DiscardSelection = { Hunks: number[] } | { Lines: DiffLineId[] };
+
+export type DiscardSource = "Head" | "Index";
+
+export type WhitespaceMode = "None" | "IgnoreAll" | "IgnoreChange" | "IgnoreEol";
+
+export type DiffAlgorithm = "Myers" | "Minimal" | "Patience";
+
+export interface DiffSettings {
+  whitespace: WhitespaceMode;
+  contextLines: number;
+  algorithm: DiffAlgorithm;
+  detectRenames: boolean;
+  detectCopies: boolean;
+  similarityThreshold: number;
+}
+
+export interface ImageData {
+  mime: string;
+  size: number;
+  width: number | null;
+  height: number | null;
+  data: string | null;
+}
+
+export interface ImageDiff {
+  old: ImageData | null;
+  new: ImageData | null;
+}
+
+export type RevisionRange = "TwoDot" | "ThreeDot";
+
+export interface RevisionDiff {
+  stats: RepoDiffStats;
+  files: DiffFile[];
+}
+
+export type SignatureStatus = "Good" | "Bad" | "UnknownKey" | "Unsigned";
+
+export interface CommitSignature {
+  commit: string;
+  status: SignatureStatus;
+  signer: string | null;
+}
+
+export interface HookOutput {
+  hook: string;
+  line: string;
+}
+
+export interface Identity {
+  name: string;
+  email: string;
+}
+
+export interface CommitRules {
+  conventional: boolean;
+  types: string[];
+  scopes: string[];
+  maxSubjectLength: number | null;
+  blankSecondLine: boolean;
+  issueKeyPattern: string | null;
+}
+
+export type CommitRule =
+  | "Conventional"
+  | "Type"
+  | "Scope"
+  | "SubjectLength"
+  | "BlankSecondLine"
+  | "IssueKey";
+
+export interface CommitViolation {
+  rule: CommitRule;
+  line: number;
+  message: string;
+}
+
+export interface TagInfo {
+  name: string;
+  target: string;
+  targetKind: "commit" | "tree" | "blob" | null;
+  annotated: boolean;
+  tagger: Identity | null;
+  time: number | null;
+  message: string | null;
+}
+
+export interface RemoteInfo {
+  name: string;
+  url: string | null;
+  pushUrl: string | null;
+  fetchRefspecs: string[];
+  pushRefspecs: string[];
+}
+
+export type RefUpdateKind = "New" | "Updated" | "Deleted";
+
+export interface RefUpdate {
+  refname: string;
+  kind: RefUpdateKind;
+  old: string | null;
+  new: string | null;
+}
+
+export interface FetchSummary {
+  remote: string;
+  updates: RefUpdate[];
+  error: string | null;
+}
+
+export type RemoteOperation = "Fetch" | "Push" | "Clone";
+
+export interface TransferStats {
+  receivedObjects: number;
+  indexedObjects: number;
+  totalObjects: number;
+  indexedDeltas: number;
+  totalDeltas: number;
+  bytes: number;
+  bytesPerSecond: number;
+}
+
+export type RemoteProgress =
+  | { Transfer: TransferStats }
+  | { Sideband: string }
+  | { RefUpdate: RefUpdate };
+
+export interface RemoteProgressEvent {
+  operation: RemoteOperation;
+  remote: string;
+  progress: RemoteProgress;
+}
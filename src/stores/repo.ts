@@ -5,6 +5,7 @@ const useRepoStore = defineStore("repoStore", {
     repo: null as string | null,
     activeBranch: null as string | null,
     selectedFile: null as string | null,
+    selectedFileStaged: false,
   }),
   actions: {
     setRepo(newRepo: string) {
@@ -14,8 +15,9 @@ const useRepoStore = defineStore("repoStore", {
       this.activeBranch = newBranch;
     },
 
-    setSelectedFile(newFile: string) {
+    setSelectedFile(newFile: string, staged = false) {
       this.selectedFile = newFile;
+      this.selectedFileStaged = staged;
     },
   },
 });